@@ -0,0 +1,137 @@
+// Beaufort scale - sea state described by wind force from 0 (calm) to 12
+// (hurricane), with WMO limits of mean wind speed at 10 m above the sea,
+// and probable height of waves in the open sea
+// Every force maps to simulation parameters:
+// - wind speed in the middle of the force's range
+// - spectrum: light winds build fully developed sea within a short distance
+//   (Pierson-Moskowitz), stronger winds need far longer fetch than a typical
+//   one, so their sea is still developing (JONSWAP), and the fetch is such,
+//   that the waves grow to the probable height of the force
+// - choppiness and foam grow with the force: white horses appear at force 4,
+//   foam streaks at force 7, and the sea is white at force 12
+
+use crate::consts;
+use crate::wave::Wind;
+
+pub struct BeaufortForce {
+   pub number: u32,
+   pub description: &'static str,
+   // limits of mean wind speed, m/s
+   pub wind_speed_range: (f32, f32),
+   // meters, in the open sea far from land
+   pub probable_wave_height: f32,
+}
+
+pub static BEAUFORT_SCALE: [BeaufortForce; 13] = [
+   BeaufortForce { number: 0, description: "Calm", wind_speed_range: (0.0, 0.5), probable_wave_height: 0.0 },
+   BeaufortForce { number: 1, description: "Light air", wind_speed_range: (0.5, 1.5), probable_wave_height: 0.1 },
+   BeaufortForce { number: 2, description: "Light breeze", wind_speed_range: (1.6, 3.3), probable_wave_height: 0.2 },
+   BeaufortForce { number: 3, description: "Gentle breeze", wind_speed_range: (3.4, 5.4), probable_wave_height: 0.6 },
+   BeaufortForce { number: 4, description: "Moderate breeze", wind_speed_range: (5.5, 7.9), probable_wave_height: 1.0 },
+   BeaufortForce { number: 5, description: "Fresh breeze", wind_speed_range: (8.0, 10.7), probable_wave_height: 2.0 },
+   BeaufortForce { number: 6, description: "Strong breeze", wind_speed_range: (10.8, 13.8), probable_wave_height: 3.0 },
+   BeaufortForce { number: 7, description: "Near gale", wind_speed_range: (13.9, 17.1), probable_wave_height: 4.0 },
+   BeaufortForce { number: 8, description: "Gale", wind_speed_range: (17.2, 20.7), probable_wave_height: 5.5 },
+   BeaufortForce { number: 9, description: "Strong gale", wind_speed_range: (20.8, 24.4), probable_wave_height: 7.0 },
+   BeaufortForce { number: 10, description: "Storm", wind_speed_range: (24.5, 28.4), probable_wave_height: 9.0 },
+   BeaufortForce { number: 11, description: "Violent storm", wind_speed_range: (28.5, 32.6), probable_wave_height: 11.5 },
+   BeaufortForce { number: 12, description: "Hurricane", wind_speed_range: (32.7, 40.0), probable_wave_height: 14.0 },
+];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BeaufortSpectrum {
+   PiersonMoskowitz,
+   Jonswap { fetch_km: f32 },
+}
+
+// Parameters of the simulation, that match a force of Beaufort scale
+#[derive(Copy, Clone, Debug)]
+pub struct BeaufortSeaState {
+   pub wind_speed: f32,
+   pub spectrum: BeaufortSpectrum,
+   pub spectrum_amplitude: f32,
+   pub choppiness: f32,
+   // see WaterLighting
+   pub foam_threshold: f32,
+   pub foam_intensity: f32,
+}
+
+impl BeaufortForce {
+   pub fn sea_state(&self) -> BeaufortSeaState {
+      let (min_speed, max_speed) = self.wind_speed_range;
+      // the spectra are undefined without wind
+      let wind_speed = f32::max(0.5 * (min_speed + max_speed), consts::BEAUFORT_MIN_WIND_VELOCITY);
+      let force = self.number as f32 / 12.0;
+      BeaufortSeaState {
+         wind_speed,
+         spectrum: if self.number <= 4 {
+            BeaufortSpectrum::PiersonMoskowitz
+         } else {
+            BeaufortSpectrum::Jonswap { fetch_km: self.fetch_meters(wind_speed) / 1000.0 }
+         },
+         spectrum_amplitude: consts::SPECTRUM_AMPLITUDE,
+         choppiness: 0.5 + force,
+         foam_threshold: -0.3 + 0.9 * force,
+         foam_intensity: if self.number < 4 { 0.0 } else { f32::min(0.6 + 0.4 * (self.number - 4) as f32 / 3.0, 1.0) },
+      }
+   }
+
+   // Fetch-limited growth of JONSWAP (Hasselmann et al., 1973):
+   // Hs = 1.6e-3 * U * sqrt(F / g), solved for the fetch F
+   fn fetch_meters(&self, wind_speed: f32) -> f32 {
+      let wave_height_ratio = self.probable_wave_height / (1.6e-3 * wind_speed);
+      consts::G * wave_height_ratio * wave_height_ratio
+   }
+}
+
+impl BeaufortSeaState {
+   pub fn wind(&self, direction: glam::Vec2) -> Wind {
+      Wind::new(self.wind_speed, direction)
+   }
+}
+
+// Force of the given wind speed (m/s)
+pub fn force_of_wind(wind_speed: f32) -> &'static BeaufortForce {
+   BEAUFORT_SCALE.iter()
+      .find(|force| wind_speed < force.wind_speed_range.1)
+      .unwrap_or(&BEAUFORT_SCALE[12])
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::wave::{WaveSpectrum, PiersonMoskowitzSpectrum, JonswapSpectrum};
+
+   // Wind of every sea state is within its own force, so the UI shows
+   // the chosen force back
+   #[test]
+   fn sea_state_wind_matches_force() {
+      for force in BEAUFORT_SCALE.iter() {
+         assert_eq!(force_of_wind(force.sea_state().wind_speed).number, force.number);
+      }
+   }
+   // Stronger wind raises higher waves, also after the switch from fully
+   // developed sea to the fetch-limited one
+   #[test]
+   fn energy_grows_with_force() {
+      // integral of the frequency spectrum (variance of heights) by midpoint rule
+      let frequency_step = 0.001;
+      let mut previous_energy = 0.0;
+      for force in BEAUFORT_SCALE.iter() {
+         let sea_state = force.sea_state();
+         let wind = sea_state.wind(glam::vec2(1.0, 0.0));
+         let spectrum: Box<dyn WaveSpectrum> = match sea_state.spectrum {
+            BeaufortSpectrum::PiersonMoskowitz => Box::new(PiersonMoskowitzSpectrum),
+            BeaufortSpectrum::Jonswap { fetch_km } => Box::new(JonswapSpectrum {
+               peak_enhancement: consts::JONSWAP_PEAK_ENHANCEMENT, fetch: fetch_km * 1000.0,
+            }),
+         };
+         let energy = sea_state.spectrum_amplitude * (0..50_000)
+            .map(|idx| spectrum.frequency_spectrum((idx as f32 + 0.5) * frequency_step, &wind))
+            .sum::<f32>() * frequency_step;
+         assert!(energy > previous_energy,
+            "Force {} has energy {}, below {} of the weaker force", force.number, energy, previous_energy);
+         previous_energy = energy;
+      }
+   }
+}
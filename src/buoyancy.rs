@@ -0,0 +1,409 @@
+// Rigid bodies floating on the water surface
+// Every body is a closed triangle mesh (hull), and the water acts only
+// on the submerged part of it:
+// - hydrostatic pressure (rho * g * depth), that pushes every submerged
+//   triangle inwards, summed over the hull it gives buoyancy and the righting
+//   torque, which makes boats pitch and roll in the waves
+// - pressure drag on triangles, that move against the water
+// - linear and angular damping, proportional to the submerged part of the hull
+// Triangles crossing the surface are clipped by the water line (linearly
+// interpolated between vertices), and water height is queried at vertices only
+
+use crate::consts;
+use glam::{Vec2, Vec3, Quat};
+use std::path::Path;
+
+// Closed triangle mesh in local coordinates of a body,
+// triangles are counter-clockwise when looking from outside
+#[derive(Clone)]
+pub struct Hull {
+   pub positions: Vec<Vec3>,
+   pub indices: Vec<u32>,
+}
+
+impl Hull {
+   // Box with given sides along local X, Y, Z axes
+   pub fn cuboid(size: Vec3) -> Self {
+      let half = size * 0.5;
+      let positions = (0..8).map(|corner| Vec3::new(
+         if corner & 1 == 0 { -half.x } else { half.x },
+         if corner & 2 == 0 { -half.y } else { half.y },
+         if corner & 4 == 0 { -half.z } else { half.z },
+      )).collect();
+      let faces: [[u32; 4]; 6] = [
+         [0, 2, 6, 4], [1, 5, 7, 3], // -X, +X
+         [0, 4, 5, 1], [2, 3, 7, 6], // -Y, +Y
+         [0, 1, 3, 2], [4, 6, 7, 5], // -Z, +Z
+      ];
+      let indices = faces.iter()
+         .flat_map(|face| [face[0], face[1], face[2], face[0], face[2], face[3]])
+         .collect();
+      let mut hull = Self { positions, indices };
+      hull.orient_outwards();
+      hull
+   }
+
+   // Cylinder with hemispherical caps, lying along local X axis
+   // (length doesn't include the caps)
+   pub fn capsule(radius: f32, length: f32, segments: usize) -> Self {
+      let segments = usize::max(segments, 3);
+      let rings_per_cap = usize::max(segments / 4, 1);
+      let mut positions = Vec::new();
+      for cap_sign in [-1.0_f32, 1.0] {
+         for ring in 0..=rings_per_cap {
+            // from the pole to the equator, or from the equator to the pole
+            let latitude = if cap_sign < 0.0 {
+               -0.5 * consts::PI * (1.0 - ring as f32 / rings_per_cap as f32)
+            } else {
+               0.5 * consts::PI * ring as f32 / rings_per_cap as f32
+            };
+            for segment in 0..segments {
+               let longitude = 2.0 * consts::PI * segment as f32 / segments as f32;
+               positions.push(Vec3::new(
+                  radius * latitude.sin() + cap_sign * 0.5 * length,
+                  radius * latitude.cos() * longitude.cos(),
+                  radius * latitude.cos() * longitude.sin()));
+            }
+         }
+      }
+      let rings = 2 * (rings_per_cap + 1);
+      let vertex_index = |ring: usize, segment: usize| (ring * segments + segment % segments) as u32;
+      let indices = (0..rings - 1).flat_map(|ring| (0..segments).flat_map(move |segment| [
+         vertex_index(ring, segment), vertex_index(ring + 1, segment), vertex_index(ring, segment + 1),
+         vertex_index(ring, segment + 1), vertex_index(ring + 1, segment), vertex_index(ring + 1, segment + 1),
+      ])).collect();
+      let mut hull = Self { positions, indices };
+      hull.orient_outwards();
+      hull
+   }
+
+   // Vertices and faces of Wavefront OBJ file (polygons are triangulated as fans),
+   // the hull is moved, so that its center of mass is at the local origin
+   // Clockwise files (negative volume) are turned inside out
+   pub fn from_obj(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+      let mut positions = Vec::new();
+      let mut indices = Vec::new();
+      for line in std::fs::read_to_string(path)?.lines() {
+         let mut tokens = line.split_whitespace();
+         match tokens.next() {
+            Some("v") => {
+               let coords = tokens.take(3).map(str::parse::<f32>).collect::<Result<Vec<_>, _>>()?;
+               if coords.len() != 3 {
+                  return Err(format!("Vertex with {} coordinates: {}", coords.len(), line).into());
+               }
+               positions.push(Vec3::new(coords[0], coords[1], coords[2]));
+            }
+            Some("f") => {
+               // "f 1 2 3", "f 1/1 2/2 3/3", "f 1/1/1 2/2/2 3/3/3", negative are relative to the end
+               let face = tokens.map(|token| {
+                  let index = token.split('/').next().unwrap_or("").parse::<i64>()?;
+                  Ok(if index < 0 { positions.len() as i64 + index } else { index - 1 })
+               }).collect::<Result<Vec<_>, std::num::ParseIntError>>()?;
+               if face.iter().any(|&index| index < 0 || index >= positions.len() as i64) {
+                  return Err(format!("Face refers to missing vertex: {}", line).into());
+               }
+               for corner in 1..face.len().saturating_sub(1) {
+                  indices.extend([face[0] as u32, face[corner] as u32, face[corner + 1] as u32]);
+               }
+            }
+            _ => {}
+         }
+      }
+      if indices.is_empty() {
+         return Err(format!("No faces in {}", path.display()).into());
+      }
+      let mut hull = Self { positions, indices };
+      if hull.volume() < 0.0 {
+         hull.indices.chunks_exact_mut(3).for_each(|triangle| triangle.swap(1, 2));
+      }
+      let center = hull.center_of_mass();
+      hull.positions.iter_mut().for_each(|position| *position -= center);
+      Ok(hull)
+   }
+
+   pub fn triangles(&self) -> impl Iterator<Item = [Vec3; 3]> + '_ {
+      self.indices.chunks_exact(3).map(move |triangle| [
+         self.positions[triangle[0] as usize],
+         self.positions[triangle[1] as usize],
+         self.positions[triangle[2] as usize],
+      ])
+   }
+
+   // Sum of signed volumes of tetrahedrons from origin to every triangle
+   pub fn volume(&self) -> f32 {
+      self.triangles().map(|[a, b, c]| a.dot(b.cross(c)) / 6.0).sum()
+   }
+
+   // Of uniformly dense body
+   pub fn center_of_mass(&self) -> Vec3 {
+      let volume = self.volume();
+      if volume.abs() < f32::EPSILON {
+         return Vec3::ZERO;
+      }
+      self.triangles()
+         .map(|[a, b, c]| a.dot(b.cross(c)) / 6.0 * (a + b + c) / 4.0)
+         .fold(Vec3::ZERO, |sum, moment| sum + moment) / volume
+   }
+
+   pub fn bounds(&self) -> (Vec3, Vec3) {
+      self.positions.iter().fold((Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+         |(min, max), &position| (min.min(position), max.max(position)))
+   }
+
+   // For convex hulls, every triangle should face away from the center
+   fn orient_outwards(&mut self) {
+      let positions = &self.positions;
+      let center = positions.iter().fold(Vec3::ZERO, |sum, &position| sum + position)
+         / positions.len() as f32;
+      for triangle in self.indices.chunks_exact_mut(3) {
+         let [a, b, c] = [0, 1, 2].map(|corner| positions[triangle[corner] as usize]);
+         if (b - a).cross(c - a).dot((a + b + c) / 3.0 - center) < 0.0 {
+            triangle.swap(1, 2);
+         }
+      }
+   }
+}
+
+pub struct RigidBody {
+   pub hull: Hull,
+   pub mass: f32,
+   // diagonal of inertia tensor in local coordinates
+   inertia: Vec3,
+   pub position: Vec3,
+   pub orientation: Quat,
+   pub velocity: Vec3,
+   pub angular_velocity: Vec3,
+}
+
+impl RigidBody {
+   // Body of uniform density (kg/m^3), with its center of mass at the given position
+   // Inertia is approximated by the one of solid box around the hull
+   pub fn new(hull: Hull, density: f32, position: Vec3) -> Self {
+      let mass = density * hull.volume().abs();
+      let (min, max) = hull.bounds();
+      let size2 = (max - min) * (max - min);
+      let inertia = mass / 12.0 * Vec3::new(size2.y + size2.z, size2.x + size2.z, size2.x + size2.y);
+      Self {
+         hull,
+         mass,
+         inertia: inertia.max(Vec3::splat(f32::EPSILON)),
+         position,
+         orientation: Quat::IDENTITY,
+         velocity: Vec3::ZERO,
+         angular_velocity: Vec3::ZERO,
+      }
+   }
+
+   // Local to world coordinates
+   pub fn transform(&self) -> glam::Affine3A {
+      glam::Affine3A::from_rotation_translation(self.orientation, self.position)
+   }
+
+   // Angular velocity to angular momentum, in world coordinates
+   fn angular_momentum(&self) -> Vec3 {
+      let local = self.orientation.inverse() * self.angular_velocity;
+      self.orientation * (local * self.inertia)
+   }
+
+   fn set_angular_momentum(&mut self, momentum: Vec3) {
+      let local = self.orientation.inverse() * momentum;
+      self.angular_velocity = self.orientation * (local / self.inertia);
+   }
+}
+
+// Forces acting on one body during a step, and how much of it is under water
+#[derive(Default)]
+struct WaterForces {
+   force: Vec3,
+   torque: Vec3,
+   submerged_area: f32,
+   total_area: f32,
+}
+
+#[derive(Copy, Clone)]
+pub struct BuoyancyParameters {
+   pub water_density: f32, // kg/m^3
+   pub drag_coefficient: f32,
+   // fraction of velocity lost per second, when fully submerged
+   pub linear_damping: f32,
+   pub angular_damping: f32,
+   // steps are split into several smaller ones for stability
+   pub substeps: usize,
+}
+
+impl Default for BuoyancyParameters {
+   fn default() -> Self {
+      Self {
+         water_density: consts::WATER_DENSITY,
+         drag_coefficient: 1.0,
+         linear_damping: 0.5,
+         angular_damping: 1.0,
+         substeps: 4,
+      }
+   }
+}
+
+#[derive(Default)]
+pub struct BuoyancySimulation {
+   pub bodies: Vec<RigidBody>,
+   pub parameters: BuoyancyParameters,
+}
+
+impl BuoyancySimulation {
+   // Advances all bodies by time_step seconds, water_height gives
+   // the elevation of water surface at horizontal world coordinates (x, z)
+   pub fn step(&mut self, time_step: f32, water_height: impl Fn(Vec2) -> f32) {
+      let substeps = usize::max(self.parameters.substeps, 1);
+      let dt = time_step / substeps as f32;
+      for _ in 0..substeps {
+         for idx in 0..self.bodies.len() {
+            let forces = self.water_forces(&self.bodies[idx], &water_height);
+            Self::integrate(&mut self.bodies[idx], &forces, &self.parameters, dt);
+         }
+      }
+   }
+
+   fn water_forces(&self, body: &RigidBody, water_height: &impl Fn(Vec2) -> f32) -> WaterForces {
+      let transform = body.transform();
+      let world_positions = body.hull.positions.iter()
+         .map(|&position| transform.transform_point3(position))
+         .collect::<Vec<_>>();
+      let depths = world_positions.iter()
+         .map(|position| water_height(Vec2::new(position.x, position.z)) - position.y)
+         .collect::<Vec<_>>();
+
+      let density = self.parameters.water_density;
+      let mut forces = WaterForces::default();
+      for triangle in body.hull.indices.chunks_exact(3) {
+         let corners = [0, 1, 2].map(|corner| triangle[corner] as usize)
+            .map(|idx| (world_positions[idx], depths[idx]));
+         forces.total_area += 0.5 * (corners[1].0 - corners[0].0)
+            .cross(corners[2].0 - corners[0].0).length();
+
+         let submerged = clip_below_surface(&corners);
+         for corner in 1..submerged.len().saturating_sub(1) {
+            let (a, b, c) = (submerged[0], submerged[corner], submerged[corner + 1]);
+            // outward normal, scaled by the area
+            let area_normal = 0.5 * (b.0 - a.0).cross(c.0 - a.0);
+            let area = area_normal.length();
+            if area < f32::EPSILON {
+               continue;
+            }
+            let normal = area_normal / area;
+            let depth_sum = a.1 + b.1 + c.1;
+            if depth_sum < f32::EPSILON {
+               continue;
+            }
+            // pressure grows linearly with depth, so it's applied closer to the deeper
+            // corners (exact integral of position times depth over the triangle)
+            let pressure_center = (a.0 * a.1 + b.0 * b.1 + c.0 * c.1
+               + (a.0 + b.0 + c.0) * depth_sum) / (4.0 * depth_sum);
+            let pressure_force = -density * consts::G * depth_sum / 3.0 * area_normal;
+            forces.force += pressure_force;
+            forces.torque += (pressure_center - body.position).cross(pressure_force);
+
+            let arm = (a.0 + b.0 + c.0) / 3.0 - body.position;
+            let normal_speed = (body.velocity + body.angular_velocity.cross(arm)).dot(normal);
+            if normal_speed > 0.0 {
+               let drag_force = -0.5 * density * self.parameters.drag_coefficient
+                  * area * normal_speed * normal_speed * normal;
+               forces.force += drag_force;
+               forces.torque += arm.cross(drag_force);
+            }
+            forces.submerged_area += area;
+         }
+      }
+      forces
+   }
+
+   // Semi-implicit Euler, rotation is integrated with angular momentum
+   // (angular velocity of a freely rotating body changes, unless it's a sphere)
+   fn integrate(body: &mut RigidBody, forces: &WaterForces, parameters: &BuoyancyParameters, dt: f32) {
+      let submerged = forces.submerged_area / f32::max(forces.total_area, f32::EPSILON);
+      body.velocity += (forces.force / body.mass - Vec3::new(0.0, consts::G, 0.0)) * dt;
+      body.velocity *= f32::max(1.0 - parameters.linear_damping * submerged * dt, 0.0);
+      let angular_momentum = body.angular_momentum() + forces.torque * dt;
+      body.set_angular_momentum(angular_momentum
+         * f32::max(1.0 - parameters.angular_damping * submerged * dt, 0.0));
+
+      body.position += body.velocity * dt;
+      let spin = Quat::from_xyzw(body.angular_velocity.x, body.angular_velocity.y,
+         body.angular_velocity.z, 0.0) * body.orientation;
+      body.orientation = (body.orientation + spin * (0.5 * dt)).normalize();
+   }
+}
+
+// Part of triangle with positive depth (Sutherland-Hodgman clipping of one plane),
+// corners are pairs of world position and depth below the surface
+fn clip_below_surface(corners: &[(Vec3, f32); 3]) -> Vec<(Vec3, f32)> {
+   let mut polygon = Vec::with_capacity(4);
+   for idx in 0..3 {
+      let (current, next) = (corners[idx], corners[(idx + 1) % 3]);
+      if current.1 > 0.0 {
+         polygon.push(current);
+      }
+      if (current.1 > 0.0) != (next.1 > 0.0) {
+         let t = current.1 / (current.1 - next.1);
+         polygon.push((current.0.lerp(next.0, t), 0.0));
+      }
+   }
+   polygon
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn hull_volumes() {
+      let cuboid = Hull::cuboid(Vec3::new(2.0, 3.0, 4.0));
+      assert!((cuboid.volume() - 24.0).abs() < 1e-4);
+
+      let (radius, length) = (1.0, 2.0);
+      let expected = consts::PI * radius * radius * (length + 4.0 / 3.0 * radius);
+      let capsule = Hull::capsule(radius, length, 64);
+      assert!((capsule.volume() - expected).abs() / expected < 0.01,
+         "capsule volume {} vs {}", capsule.volume(), expected);
+   }
+
+   // Floating box sinks until it displaces water of its own mass
+   #[test]
+   fn box_floats_at_archimedes_draft() {
+      let height = 2.0;
+      let density = 0.25 * consts::WATER_DENSITY;
+      let mut simulation = BuoyancySimulation::default();
+      simulation.bodies.push(RigidBody::new(
+         Hull::cuboid(Vec3::new(4.0, height, 3.0)), density, Vec3::new(0.0, 2.0, 0.0)));
+      for _ in 0..3600 {
+         simulation.step(1.0 / 60.0, |_| 0.0);
+      }
+      let body = &simulation.bodies[0];
+      let draft = 0.5 * height - body.position.y;
+      assert!((draft - 0.25 * height).abs() < 0.02, "draft {}", draft);
+      assert!(body.velocity.length() < 0.05, "velocity {}", body.velocity);
+   }
+
+   // Cube with clockwise faces in the file still floats
+   #[test]
+   fn clockwise_obj_hull_floats() {
+      let cuboid = Hull::cuboid(Vec3::splat(2.0));
+      let mut obj = cuboid.positions.iter()
+         .map(|position| format!("v {} {} {}\n", position.x, position.y, position.z))
+         .collect::<String>();
+      for triangle in cuboid.indices.chunks_exact(3) {
+         obj += &format!("f {} {} {}\n", triangle[0] + 1, triangle[2] + 1, triangle[1] + 1);
+      }
+      let path = std::env::temp_dir().join("fft-water-clockwise-cube.obj");
+      std::fs::write(&path, obj).unwrap();
+      let hull = Hull::from_obj(&path).unwrap();
+      let _ = std::fs::remove_file(&path);
+      assert!((hull.volume() - 8.0).abs() < 1e-4, "volume {}", hull.volume());
+
+      let mut simulation = BuoyancySimulation::default();
+      simulation.bodies.push(RigidBody::new(hull, 0.5 * consts::WATER_DENSITY, Vec3::new(0.0, 1.0, 0.0)));
+      for _ in 0..600 {
+         simulation.step(1.0 / 60.0, |_| 0.0);
+      }
+      assert!(simulation.bodies[0].position.y > -0.5, "height {}", simulation.bodies[0].position.y);
+   }
+}
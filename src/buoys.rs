@@ -0,0 +1,130 @@
+// Virtual wave buoys - fixed points on the water surface, that record
+// time series of the surface motion, like real measurement buoys do
+// Vertical velocity and acceleration are found by finite differences
+// between consecutive records, so they're as precise as the time step
+
+use crate::consts;
+use crate::export::ExportResult;
+use crate::height_field_cpu::Displacement;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Copy, Clone)]
+pub struct BuoyRecord {
+   pub time: f32,
+   pub elevation: f32,
+   // horizontal displacement of water at the buoy
+   pub dx: f32,
+   pub dz: f32,
+   pub vertical_velocity: f32,
+   pub vertical_acceleration: f32,
+}
+
+pub struct Buoy {
+   // horizontal world coordinates (x, z)
+   pub position: glam::Vec2,
+   // at most BUOY_MAX_RECORDS of the latest ones, the oldest tenth of them
+   // is dropped at once, when the limit is reached
+   records: Vec<BuoyRecord>,
+}
+
+impl Buoy {
+   pub fn new(position: glam::Vec2) -> Self {
+      Self { position, records: Vec::new() }
+   }
+
+   // Displacement of water right at the buoy position at the given time,
+   // records that are not newer than the last one are ignored
+   pub fn record(&mut self, time: f32, displacement: Displacement) {
+      let (vertical_velocity, vertical_acceleration) = match self.records.last() {
+         Some(last) if time <= last.time => return,
+         Some(last) => {
+            let dt = time - last.time;
+            let velocity = (displacement.height - last.elevation) / dt;
+            // the first record has no velocity to differentiate
+            let acceleration = if self.records.len() > 1 {
+               (velocity - last.vertical_velocity) / dt
+            } else {
+               0.0
+            };
+            (velocity, acceleration)
+         }
+         None => (0.0, 0.0),
+      };
+      if self.records.len() >= consts::BUOY_MAX_RECORDS {
+         self.records.drain(..consts::BUOY_MAX_RECORDS / 10);
+      }
+      self.records.push(BuoyRecord {
+         time,
+         elevation: displacement.height,
+         dx: displacement.dx,
+         dz: displacement.dz,
+         vertical_velocity,
+         vertical_acceleration,
+      });
+   }
+
+   pub fn records(&self) -> &[BuoyRecord] {
+      &self.records
+   }
+
+   pub fn clear(&mut self) {
+      self.records.clear();
+   }
+
+   // Elevations of the last count records, the oldest first
+   pub fn recent_elevations(&self, count: usize) -> Vec<f32> {
+      let start = self.records.len().saturating_sub(count);
+      self.records[start..].iter().map(|record| record.elevation).collect()
+   }
+}
+
+// Records of all buoys in one table, one row per record
+// Returns the number of written rows
+pub fn write_csv(path: &Path, buoys: &[Buoy]) -> ExportResult<usize> {
+   let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+   writeln!(file, "buoy,x,z,time,elevation,dx,dz,vertical_velocity,vertical_acceleration")?;
+   let mut rows = 0;
+   for (idx, buoy) in buoys.iter().enumerate() {
+      for record in buoy.records() {
+         writeln!(file, "{},{},{},{},{},{},{},{},{}", idx, buoy.position.x, buoy.position.y,
+            record.time, record.elevation, record.dx, record.dz,
+            record.vertical_velocity, record.vertical_acceleration)?;
+         rows += 1;
+      }
+   }
+   Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // Elevation a*sin(w*t) has velocity a*w*cos(w*t) and acceleration -a*w^2*sin(w*t)
+   #[test]
+   fn sine_elevation_derivatives() {
+      let (amplitude, frequency, dt) = (2.0, 1.5, 0.01);
+      let elevation = |time: f32| amplitude * f32::sin(frequency * time);
+      let mut buoy = Buoy::new(glam::Vec2::ZERO);
+      for step in 0..=100 {
+         let time = step as f32 * dt;
+         buoy.record(time, Displacement { height: elevation(time), ..Displacement::default() });
+      }
+      // not newer than the last one
+      buoy.record(0.5, Displacement { height: 100.0, ..Displacement::default() });
+      buoy.record(1.0, Displacement { height: 100.0, ..Displacement::default() });
+      assert_eq!(buoy.records().len(), 101);
+
+      let records = buoy.records();
+      assert_eq!((records[0].vertical_velocity, records[0].vertical_acceleration), (0.0, 0.0));
+      assert_eq!(records[1].vertical_acceleration, 0.0);
+      let last = records.last().unwrap();
+      assert_eq!(last.elevation, elevation(1.0));
+      // backward differences lag behind by half a step for velocity, and a step for acceleration
+      let velocity = amplitude * frequency * f32::cos(frequency * (1.0 - 0.5 * dt));
+      let acceleration = -amplitude * frequency * frequency * f32::sin(frequency * (1.0 - dt));
+      assert!((last.vertical_velocity - velocity).abs() < 1e-3, "{} vs {}", last.vertical_velocity, velocity);
+      assert!((last.vertical_acceleration - acceleration).abs() < 2e-2,
+         "{} vs {}", last.vertical_acceleration, acceleration);
+   }
+}
@@ -49,24 +49,11 @@ impl Camera {
       self
    }
 
-   pub fn with_orthographic(&mut self, bounds: glam::Vec3) -> &mut Self {
-      self.projection_matrix = glam::Mat4::orthographic_rh_gl(
-         -bounds.x, bounds.x,
-         -bounds.y, bounds.y,
-         -bounds.z, bounds.z);
-      self.is_merged.set( false);
-      self
-   }
-
    pub fn view(&self) -> &glam::Affine3A {
       &self.view_matrix
    }
 
-   pub fn projection(&self) -> &glam::Mat4 {
-      &self.projection_matrix
-   }
-
-   pub fn view_projection(&self) -> Ref<glam::Mat4> {
+   pub fn view_projection(&self) -> Ref<'_, glam::Mat4> {
       if !self.is_merged.get()  {
          *self.view_projection_matrix.borrow_mut() = self.projection_matrix * self.view_matrix;
          self.is_merged.set(true);
@@ -77,18 +64,6 @@ impl Camera {
    pub fn position(&self) -> glam::Vec3A {
       self.view_matrix.translation
    }
-
-   pub fn x_axis(&self) -> glam::Vec3A {
-      self.view_matrix.x_axis
-   }
-
-   pub fn y_axis(&self) -> glam::Vec3A {
-      self.view_matrix.y_axis
-   }
-
-   pub fn z_axis(&self) -> glam::Vec3A {
-      self.view_matrix.z_axis
-   }
 }
 
 impl Default for Camera {
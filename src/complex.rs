@@ -7,3 +7,13 @@ pub fn complex_exp(complex: glam::Vec2) -> glam::Vec2 {
    let sin = f32::sin(complex.y);
 	glam::vec2(cos, sin) * exp_real
 }
+
+// computing a*b
+pub fn complex_mul(a: glam::Vec2, b: glam::Vec2) -> glam::Vec2 {
+   glam::vec2(a.x*b.x - a.y*b.y, a.x*b.y + a.y*b.x)
+}
+
+// computing a^*
+pub fn complex_conjugate(c: glam::Vec2) -> glam::Vec2 {
+   glam::vec2(c.x, -c.y)
+}
@@ -0,0 +1,242 @@
+// SimulationConfig - parameters of the demo, that can be saved to a file
+// and loaded back (in the UI, or with --config <path> on the command line)
+// The format is chosen by file extension: .json or .toml
+// Missing fields take default values, so older or hand-written files
+// need to list only what they change
+
+use crate::consts;
+use crate::render::DrawMode;
+use crate::wave::Wind;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub type ConfigResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulationConfig {
+   // has to be a power of 2, from 16 to 1024
+   pub lattice_size: usize,
+   pub facet_size: f32,
+   pub period_sec: f32,
+   pub choppiness: f32,
+   pub wind_speed: f32,
+   // angle from X axis towards Z axis
+   pub wind_direction_deg: f32,
+   pub spectrum_amplitude: f32,
+   pub length_cutoff_meters: f32,
+   // from 0 to i32::MAX, the UI edits it as i32
+   pub seed: u64,
+   // meters, none for deep water
+   #[serde(skip_serializing_if = "Option::is_none")]
+   pub water_depth: Option<f32>,
+   pub draw_mode: DrawMode,
+   // tables go last, TOML doesn't allow plain values after them
+   pub camera: CameraConfig,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+   pub position: [f32; 3],
+   pub yaw_deg: f32,
+   pub pitch_deg: f32,
+}
+
+impl Default for SimulationConfig {
+   fn default() -> Self {
+      Self {
+         lattice_size: 256,
+         facet_size: 4.0,
+         period_sec: 10.0,
+         choppiness: consts::CHOPPINESS,
+         wind_speed: consts::WIND_VELOCITY,
+         wind_direction_deg: f32::atan2(consts::WIND_DIRECTION_Y, consts::WIND_DIRECTION_X).to_degrees(),
+         spectrum_amplitude: consts::SPECTRUM_AMPLITUDE,
+         length_cutoff_meters: consts::WAVELENGTH_CUTOFF_METERS,
+         seed: consts::RANDOM_SEED,
+         water_depth: None,
+         draw_mode: DrawMode::Wireframe,
+         camera: CameraConfig::default(),
+      }
+   }
+}
+
+impl Default for CameraConfig {
+   fn default() -> Self {
+      Self {
+         position: [0.0, -20.0, -1.0],
+         yaw_deg: -90.0,
+         pitch_deg: 0.0,
+      }
+   }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum ConfigFormat {
+   Json, Toml,
+}
+
+impl ConfigFormat {
+   fn from_path(path: &Path) -> ConfigResult<Self> {
+      match path.extension().and_then(|extension| extension.to_str()) {
+         Some("json") => Ok(Self::Json),
+         Some("toml") => Ok(Self::Toml),
+         _ => Err(format!("Unknown config format of {} (expected .json or .toml)", path.display()).into()),
+      }
+   }
+}
+
+impl SimulationConfig {
+   pub fn load(path: &Path) -> ConfigResult<Self> {
+      let text = std::fs::read_to_string(path)?;
+      let config: Self = match ConfigFormat::from_path(path)? {
+         ConfigFormat::Json => serde_json::from_str(&text)?,
+         ConfigFormat::Toml => toml::from_str(&text)?,
+      };
+      config.validate()?;
+      Ok(config)
+   }
+
+   pub fn save(&self, path: &Path) -> ConfigResult<()> {
+      let text = match ConfigFormat::from_path(path)? {
+         ConfigFormat::Json => serde_json::to_string_pretty(self)?,
+         ConfigFormat::Toml => toml::to_string_pretty(self)?,
+      };
+      if let Some(directory) = path.parent() {
+         std::fs::create_dir_all(directory)?;
+      }
+      std::fs::write(path, text)?;
+      Ok(())
+   }
+
+   pub fn wind(&self) -> Wind {
+      let direction = self.wind_direction_deg.to_radians();
+      Wind::new(self.wind_speed, glam::vec2(direction.cos(), direction.sin()))
+   }
+
+   fn validate(&self) -> ConfigResult<()> {
+      let values = [
+         ("facet_size", self.facet_size),
+         ("period_sec", self.period_sec),
+         ("choppiness", self.choppiness),
+         ("wind_speed", self.wind_speed),
+         ("wind_direction_deg", self.wind_direction_deg),
+         ("spectrum_amplitude", self.spectrum_amplitude),
+         ("length_cutoff_meters", self.length_cutoff_meters),
+         ("camera.yaw_deg", self.camera.yaw_deg),
+         ("camera.pitch_deg", self.camera.pitch_deg),
+      ];
+      let camera_position = self.camera.position.iter().map(|&value| ("camera.position", value));
+      if let Some((name, value)) = values.iter().copied().chain(camera_position)
+         .chain(self.water_depth.map(|depth| ("water_depth", depth)))
+         .find(|(_, value)| !value.is_finite()) {
+         return Err(format!("Value of {} is {}, it has to be a finite number", name, value).into());
+      }
+      if !self.lattice_size.is_power_of_two() || !(16..=1024).contains(&self.lattice_size) {
+         return Err(format!("Lattice size {} is not a power of 2 from 16 to 1024", self.lattice_size).into());
+      }
+      if self.facet_size <= 0.0 || self.period_sec <= 0.0 {
+         return Err("Facet size and period have to be positive".into());
+      }
+      if self.water_depth.is_some_and(|depth| depth <= 0.0) {
+         return Err("Water depth has to be positive".into());
+      }
+      // the spectra are undefined without wind
+      if self.wind_speed < consts::BEAUFORT_MIN_WIND_VELOCITY {
+         return Err(format!("Wind speed {} m/s is below {} m/s",
+            self.wind_speed, consts::BEAUFORT_MIN_WIND_VELOCITY).into());
+      }
+      if self.choppiness < 0.0 || self.spectrum_amplitude < 0.0 || self.length_cutoff_meters < 0.0 {
+         return Err("Choppiness, spectrum amplitude and wavelength cutoff can't be negative".into());
+      }
+      if self.seed > i32::MAX as u64 {
+         return Err(format!("Seed {} is not from 0 to {}", self.seed, i32::MAX).into());
+      }
+      Ok(())
+   }
+
+   // Bundled presets, from the calmest to the roughest water
+   pub fn presets() -> Vec<(&'static str, Self)> {
+      vec![
+         ("Calm lake", Self {
+            lattice_size: 256,
+            facet_size: 0.5,
+            period_sec: 20.0,
+            choppiness: 0.5,
+            wind_speed: 2.0,
+            spectrum_amplitude: 0.5,
+            length_cutoff_meters: 0.01,
+            water_depth: Some(5.0),
+            camera: CameraConfig { position: [0.0, -5.0, -1.0], ..CameraConfig::default() },
+            ..Self::default()
+         }),
+         ("Moderate sea", Self {
+            choppiness: 1.0,
+            wind_speed: 10.0,
+            spectrum_amplitude: 1.0,
+            ..Self::default()
+         }),
+         ("Storm", Self {
+            lattice_size: 512,
+            facet_size: 4.0,
+            choppiness: 1.5,
+            wind_speed: 30.0,
+            spectrum_amplitude: 1.5,
+            length_cutoff_meters: 0.1,
+            camera: CameraConfig { position: [0.0, -40.0, -1.0], ..CameraConfig::default() },
+            ..Self::default()
+         }),
+      ]
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn save_and_load_presets() {
+      let directory = std::env::temp_dir().join("fft-water-config-test");
+      for (idx, (_, preset)) in SimulationConfig::presets().into_iter().enumerate() {
+         for extension in ["json", "toml"] {
+            let path = directory.join(format!("preset{}.{}", idx, extension));
+            preset.save(&path).unwrap();
+            assert_eq!(SimulationConfig::load(&path).unwrap(), preset);
+         }
+      }
+      let _ = std::fs::remove_dir_all(directory);
+   }
+
+   #[test]
+   fn seed_out_of_range() {
+      let path = std::env::temp_dir().join("fft-water-config-seed.json");
+      std::fs::write(&path, format!("{{\"seed\": {}}}", i32::MAX as u64 + 1)).unwrap();
+      assert!(SimulationConfig::load(&path).is_err());
+      std::fs::write(&path, format!("{{\"seed\": {}}}", i32::MAX)).unwrap();
+      assert_eq!(SimulationConfig::load(&path).unwrap().seed, i32::MAX as u64);
+      let _ = std::fs::remove_file(path);
+   }
+
+   #[test]
+   fn invalid_values() {
+      let path = std::env::temp_dir().join("fft-water-config-invalid.json");
+      for fields in ["\"wind_speed\": 0.0, \"water_depth\": 10.0", "\"wind_speed\": -5.0",
+         "\"choppiness\": -1.0", "\"spectrum_amplitude\": -0.5", "\"facet_size\": 1e40"] {
+         std::fs::write(&path, format!("{{{}}}", fields)).unwrap();
+         assert!(SimulationConfig::load(&path).is_err(), "{}", fields);
+      }
+      let _ = std::fs::remove_file(path);
+      assert!(SimulationConfig { period_sec: f32::NAN, ..SimulationConfig::default() }.validate().is_err());
+   }
+
+   // Files in presets/ are the same as the ones in the UI
+   #[test]
+   fn bundled_preset_files() {
+      let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("presets");
+      let names = ["calm_lake.toml", "moderate_sea.toml", "storm.toml"];
+      for (name, (_, preset)) in names.iter().zip(SimulationConfig::presets()) {
+         assert_eq!(SimulationConfig::load(&directory.join(name)).unwrap(), preset, "{}", name);
+      }
+   }
+}
@@ -1,12 +1,35 @@
 pub use std::f32::consts::PI;
 pub const GRAVITATIONAL_CONSTANT: f32 = 9.80665;
 pub const G: f32 = GRAVITATIONAL_CONSTANT;
+pub const WATER_DENSITY: f32 = 1025.0; // sea water, kg/m^3
 
 pub const WAVELENGTH_CUTOFF_METERS: f32 = 0.01;
-pub const PHILLIPS_SPECTRUM_AMPLITUDE: f32 = 20.0;
+pub const SPECTRUM_AMPLITUDE: f32 = 1.0;
+pub const SPECTRUM_TRANSITION_SEC: f32 = 5.0;
+pub const TIMELINE_WIND_SPEED_STEP: f32 = 0.5;
+pub const TIMELINE_WIND_DIRECTION_STEP_DEG: f32 = 5.0;
+pub const PHILLIPS_SPECTRUM_AMPLITUDE: f32 = 0.0081 * 0.5;
+pub const JONSWAP_PEAK_ENHANCEMENT: f32 = 3.3;
+pub const JONSWAP_FETCH_METERS: f32 = 100_000.0;
+pub const TMA_WATER_DEPTH_METERS: f32 = 20.0;
+pub const SPREADING_EXPONENT: f32 = 4.0;
+pub const RANDOM_SEED: u64 = 42;
+pub const CHOPPINESS: f32 = 1.0;
+pub const DISPLACEMENT_INVERSION_ITERATIONS: usize = 8;
+pub const MAX_CASCADES: usize = 4;
+pub const CASCADE_BAND_FACTOR: f32 = 4.0;
+pub const CASCADE_SIZE_RATIO: f32 = 5.3;
+pub const MIN_CASCADE_SIZE_METERS: f32 = 0.1;
+pub const READBACK_BUFFER_COUNT: usize = 3;
+// one hour of records at 60 frames per second
+pub const BUOY_MAX_RECORDS: usize = 216_000;
 pub const WIND_DIRECTION_X: f32 = 1.0;
 pub const WIND_DIRECTION_Y: f32 = 0.0;
 pub const WIND_VELOCITY: f32 = 25.0;
+pub const BEAUFORT_MIN_WIND_VELOCITY: f32 = 0.3;
+
+pub const SUN_AZIMUTH_DEGREES: f32 = 30.0;
+pub const SUN_ELEVATION_DEGREES: f32 = 25.0;
 
 pub const CAMERA_FIELD_OF_VIEW: f32 = 90.0;
 pub const CAMERA_NEAR_PLANE: f32 = 0.01;
@@ -0,0 +1,231 @@
+// Export of the simulated surface to files, for use in other tools
+// Simulation is periodic, so frames are sampled over exactly one period,
+// and the exported sequence loops seamlessly
+// Frames come from any source of DisplacementGrid (e.g. HeightField on GPU
+// via read_displacement(), or HeightFieldCpu in headless mode)
+
+use crate::height_field_cpu::DisplacementGrid;
+use serde::Serialize;
+use std::path::Path;
+
+pub type ExportResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+// PNG - 16-bit unsigned integers, values are normalized to the value range
+// TIFF - 32-bit floats, values are stored in meters as is
+#[derive(Copy, Clone, PartialEq)]
+pub enum HeightmapFormat {
+   Png16, Tiff32,
+}
+
+pub struct HeightmapExport {
+   pub format: HeightmapFormat,
+   pub frame_rate: f32,
+   // store displacement x, height, displacement z in RGB (otherwise only height)
+   pub with_horizontal: bool,
+}
+
+// The smallest and the largest value among exported channels of all frames
+#[derive(Copy, Clone)]
+pub struct ValueRange {
+   pub min: f32,
+   pub max: f32,
+}
+
+impl ValueRange {
+   pub fn normalize(&self, value: f32) -> f32 {
+      ((value - self.min) / f32::max(self.max - self.min, f32::EPSILON)).clamp(0.0, 1.0)
+   }
+}
+
+// Times of frames within one period, the last frame is just before
+// the period ends, so the first frame follows it again
+pub fn frame_times(period_sec: f32, n_frames: usize) -> ExportResult<Vec<f32>> {
+   if n_frames == 0 {
+      return Err("Export needs at least 1 frame, got 0".into());
+   }
+   Ok((0..n_frames).map(|frame| frame as f32 * period_sec / n_frames as f32).collect())
+}
+
+// Frame rate below 1/(2*period) rounds to no frames at all
+pub fn frame_count(period_sec: f32, frame_rate: f32) -> ExportResult<usize> {
+   let n_frames = (period_sec * frame_rate).round() as usize;
+   if n_frames == 0 {
+      return Err(format!("Frame rate {} gives no frames within period of {} s (raise the frame rate)",
+         frame_rate, period_sec).into());
+   }
+   Ok(n_frames)
+}
+
+// Values of displacement channels, interleaved for every grid point
+fn channel_values(grid: &DisplacementGrid, with_horizontal: bool) -> Vec<f32> {
+   if with_horizontal {
+      (0..grid.dy.len()).flat_map(|idx| [grid.dx[idx], grid.dy[idx], grid.dz[idx]]).collect()
+   } else {
+      grid.dy.clone()
+   }
+}
+
+pub fn value_range<G: std::borrow::Borrow<DisplacementGrid>>(frames: impl Iterator<Item = G>, with_horizontal: bool) -> ValueRange {
+   frames.fold(ValueRange { min: f32::MAX, max: f32::MIN }, |range, grid| {
+      channel_values(grid.borrow(), with_horizontal).iter().fold(range, |range, &value|
+         ValueRange { min: range.min.min(value), max: range.max.max(value) })
+   })
+}
+
+// Writes "heightmap_0000.png" (or ".tiff"), "heightmap_0001.png", ...
+// and "heightmaps.json" with description of the sequence
+// Returns the number of written frames
+pub fn export_heightmaps(directory: &Path, options: &HeightmapExport, period_sec: f32, physical_size: f32,
+   mut sample: impl FnMut(f32) -> DisplacementGrid) -> ExportResult<usize> {
+   std::fs::create_dir_all(directory)?;
+   let times = frame_times(period_sec, frame_count(period_sec, options.frame_rate)?)?;
+
+   // normalization needs the range in advance, so frames are computed twice
+   // (instead of keeping all of them in memory)
+   let range = value_range(times.iter().map(|&time| sample(time)), options.with_horizontal);
+
+   let extension = match options.format {
+      HeightmapFormat::Png16 => "png",
+      HeightmapFormat::Tiff32 => "tiff",
+   };
+   for (frame, &time) in times.iter().enumerate() {
+      let grid = sample(time);
+      let path = directory.join(format!("heightmap_{:04}.{}", frame, extension));
+      match options.format {
+         HeightmapFormat::Png16 => write_png16(&path, &grid, options.with_horizontal, &range)?,
+         HeightmapFormat::Tiff32 => write_tiff32(&path, &grid, options.with_horizontal)?,
+      }
+   }
+
+   write_sidecar(&directory.join("heightmaps.json"), &HeightmapSidecar {
+      frame_count: times.len(),
+      frame_rate: options.frame_rate,
+      period_sec,
+      physical_size,
+      channels: if options.with_horizontal { &["dx", "dy", "dz"] } else { &["dy"] },
+      min_value: range.min,
+      max_value: range.max,
+   })?;
+   Ok(times.len())
+}
+
+// Contents of "heightmaps.json"
+#[derive(Serialize)]
+struct HeightmapSidecar {
+   frame_count: usize,
+   frame_rate: f32,
+   period_sec: f32,
+   physical_size: f32,
+   channels: &'static [&'static str],
+   min_value: f32,
+   max_value: f32,
+}
+
+pub struct FlipbookExport {
+   pub frame_count: usize,
+   // horizontal displacement multiplier, used for normals
+   pub choppiness: f32,
+}
+
+// Writes all frames into one texture atlas, frames go row by row, left to right
+// - "displacement_atlas.png" - 16-bit RGB (dx, dy, dz), normalized to the value range
+// - "normal_atlas.png" - 8-bit tangent space normals (R - x, G - z, B - up)
+// - "flipbook.json" - description of the atlas
+// Rows of every frame go along Z axis, columns along X axis
+pub fn export_flipbook(directory: &Path, options: &FlipbookExport, period_sec: f32, physical_size: f32,
+   mut sample: impl FnMut(f32) -> DisplacementGrid) -> ExportResult<()> {
+   std::fs::create_dir_all(directory)?;
+   let frames = frame_times(period_sec, options.frame_count)?.iter()
+      .map(|&time| sample(time))
+      .collect::<Vec<_>>();
+   let range = value_range(frames.iter(), true);
+
+   let tile_size = frames[0].size;
+   let columns = f32::ceil(f32::sqrt(frames.len() as f32)) as usize;
+   let rows = frames.len().div_ceil(columns);
+   let (atlas_width, atlas_height) = ((columns * tile_size) as u32, (rows * tile_size) as u32);
+   let mut displacement_atlas = image::ImageBuffer::<image::Rgb<u16>, Vec<u16>>::new(atlas_width, atlas_height);
+   let mut normal_atlas = image::RgbImage::new(atlas_width, atlas_height);
+
+   let to_u16 = |value: f32| (range.normalize(value) * u16::MAX as f32).round() as u16;
+   let to_u8 = |value: f32| ((value * 0.5 + 0.5) * u8::MAX as f32).round() as u8;
+   for (frame, grid) in frames.iter().enumerate() {
+      let (tile_x, tile_y) = ((frame % columns * tile_size) as u32, (frame / columns * tile_size) as u32);
+      let normals = grid.normals(physical_size, options.choppiness);
+      for (idx, normal) in normals.iter().enumerate() {
+         let (x, y) = (tile_x + (idx % tile_size) as u32, tile_y + (idx / tile_size) as u32);
+         displacement_atlas.put_pixel(x, y,
+            image::Rgb([to_u16(grid.dx[idx]), to_u16(grid.dy[idx]), to_u16(grid.dz[idx])]));
+         normal_atlas.put_pixel(x, y,
+            image::Rgb([to_u8(normal.x), to_u8(normal.z), to_u8(normal.y)]));
+      }
+   }
+   displacement_atlas.save(directory.join("displacement_atlas.png"))?;
+   normal_atlas.save(directory.join("normal_atlas.png"))?;
+
+   write_sidecar(&directory.join("flipbook.json"), &FlipbookSidecar {
+      frame_count: frames.len(),
+      period_sec,
+      physical_size,
+      tile_size,
+      columns,
+      rows,
+      choppiness: options.choppiness,
+      displacement_atlas: "displacement_atlas.png",
+      normal_atlas: "normal_atlas.png",
+      min_value: range.min,
+      max_value: range.max,
+   })
+}
+
+// Contents of "flipbook.json"
+#[derive(Serialize)]
+struct FlipbookSidecar {
+   frame_count: usize,
+   period_sec: f32,
+   physical_size: f32,
+   tile_size: usize,
+   columns: usize,
+   rows: usize,
+   choppiness: f32,
+   displacement_atlas: &'static str,
+   normal_atlas: &'static str,
+   min_value: f32,
+   max_value: f32,
+}
+
+fn write_png16(path: &Path, grid: &DisplacementGrid, with_horizontal: bool, range: &ValueRange) -> ExportResult<()> {
+   let size = grid.size as u32;
+   let values = channel_values(grid, with_horizontal).iter()
+      .map(|&value| (range.normalize(value) * u16::MAX as f32).round() as u16)
+      .collect::<Vec<u16>>();
+   if with_horizontal {
+      image::ImageBuffer::<image::Rgb<u16>, _>::from_raw(size, size, values)
+         .expect("Size of RGB image doesn't match the grid").save(path)?;
+   } else {
+      image::ImageBuffer::<image::Luma<u16>, _>::from_raw(size, size, values)
+         .expect("Size of grayscale image doesn't match the grid").save(path)?;
+   }
+   Ok(())
+}
+
+// The image crate can't encode float TIFF, so the tiff crate is used directly
+fn write_tiff32(path: &Path, grid: &DisplacementGrid, with_horizontal: bool) -> ExportResult<()> {
+   use tiff::encoder::{colortype, TiffEncoder};
+   let size = grid.size as u32;
+   let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+   let mut encoder = TiffEncoder::new(file)?;
+   let values = channel_values(grid, with_horizontal);
+   if with_horizontal {
+      encoder.write_image::<colortype::RGB32Float>(size, size, &values)?;
+   } else {
+      encoder.write_image::<colortype::Gray32Float>(size, size, &values)?;
+   }
+   Ok(())
+}
+
+// Description of exported files, next to them
+pub fn write_sidecar(path: &Path, sidecar: &impl Serialize) -> ExportResult<()> {
+   std::fs::write(path, serde_json::to_string_pretty(sidecar)? + "\n")?;
+   Ok(())
+}
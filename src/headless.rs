@@ -0,0 +1,266 @@
+// Headless mode - simulation without a window or OpenGL context
+// HeightFieldCpu is stepped a fixed number of times (or its frames are
+// exported), which is suitable for batch jobs and servers without displays
+// Launched as: fft-water --headless [options]
+
+use crate::consts;
+use crate::export::{FlipbookExport, HeightmapExport, HeightmapFormat};
+use crate::mesh_export::{AnimationExport, AnimationFormat, MeshFormat, SurfaceMesh};
+use crate::height_field::SpectrumParameters;
+use crate::height_field_cpu::{DisplacementGrid, HeightFieldCpu};
+use crate::wave::Wind;
+
+pub const USAGE: &str = "Usage: fft-water --headless [options]
+   --steps <N>                number of simulation steps (default 100)
+   --time-step <sec>          simulated time between steps (default 1/30)
+   --size <N>                 lattice size, a power of 2 (default 256)
+   --physical-size <m>        side of simulated square in meters (default 1024)
+   --period <sec>             period of ocean motion (default 10)
+   --seed <N>                 seed of random waves (default 42)
+   --depth <m>                water depth, waves get TMA spectrum and slow down (default deep water)
+   --export-heightmaps <dir>  instead of stepping, write heightmaps over one period
+   --format <png16|tiff32>    format of exported heightmaps (default png16)
+   --frame-rate <N>           exported frames per second (default 30)
+   --with-xz                  export horizontal displacement too
+   --export-flipbook <dir>    instead of stepping, write flipbook atlas over one period
+   --frames <N>               number of flipbook frames (default 64)
+   --export-mesh <file>       instead of stepping, write displaced mesh (.obj, .ply or .glb)
+   --mesh-time <sec>          simulated time of exported mesh (default 0)
+   --export-animation <file>  instead of stepping, write vertex animation over one period
+                              (.pc2 point cache, or .glb with morph targets)
+   --animation-frame-rate <N> animation frames per second (default 24)
+   --choppiness <x>           horizontal displacement multiplier (default 1)";
+
+pub struct HeadlessOptions {
+   pub steps: usize,
+   pub time_step_sec: f32,
+   pub lattice_size: usize,
+   pub physical_size: f32,
+   pub period_sec: f32,
+   pub seed: u64,
+   // meters, infinite for deep water
+   pub water_depth: f32,
+   pub heightmaps_directory: Option<std::path::PathBuf>,
+   pub heightmaps: HeightmapExport,
+   pub flipbook_directory: Option<std::path::PathBuf>,
+   pub flipbook_frame_count: usize,
+   pub mesh_path: Option<std::path::PathBuf>,
+   pub mesh_time_sec: f32,
+   pub animation_path: Option<std::path::PathBuf>,
+   pub animation_frame_rate: f32,
+   pub choppiness: f32,
+}
+
+impl Default for HeadlessOptions {
+   fn default() -> Self {
+      Self {
+         steps: 100,
+         time_step_sec: 1.0 / 30.0,
+         lattice_size: 256,
+         physical_size: 1024.0,
+         period_sec: 10.0,
+         seed: consts::RANDOM_SEED,
+         water_depth: f32::INFINITY,
+         heightmaps_directory: None,
+         heightmaps: HeightmapExport {
+            format: HeightmapFormat::Png16,
+            frame_rate: 30.0,
+            with_horizontal: false,
+         },
+         flipbook_directory: None,
+         flipbook_frame_count: 64,
+         mesh_path: None,
+         mesh_time_sec: 0.0,
+         animation_path: None,
+         animation_frame_rate: 24.0,
+         choppiness: consts::CHOPPINESS,
+      }
+   }
+}
+
+impl HeadlessOptions {
+   // Command line arguments (without program name), unknown ones are an error
+   pub fn from_args(args: &[String]) -> Result<Self, String> {
+      let mut options = Self::default();
+      let mut args = args.iter();
+      while let Some(arg) = args.next() {
+         match arg.as_str() {
+            "--headless" => continue,
+            "--with-xz" => {
+               options.heightmaps.with_horizontal = true;
+               continue;
+            }
+            _ => {}
+         }
+         let value = args.next()
+            .ok_or_else(|| format!("Missing value of argument {}", arg))?;
+         match arg.as_str() {
+            "--steps" => options.steps = parse_value(arg, value)?,
+            "--time-step" => options.time_step_sec = parse_value(arg, value)?,
+            "--size" => options.lattice_size = parse_value(arg, value)?,
+            "--physical-size" => options.physical_size = parse_value(arg, value)?,
+            "--period" => options.period_sec = parse_value(arg, value)?,
+            "--seed" => options.seed = parse_value(arg, value)?,
+            "--depth" => options.water_depth = parse_value(arg, value)?,
+            "--export-heightmaps" => options.heightmaps_directory = Some(value.into()),
+            "--format" => options.heightmaps.format = match value.as_str() {
+               "png16" => HeightmapFormat::Png16,
+               "tiff32" => HeightmapFormat::Tiff32,
+               _ => return Err(format!("Unknown heightmap format {}", value)),
+            },
+            "--frame-rate" => options.heightmaps.frame_rate = parse_value(arg, value)?,
+            "--export-flipbook" => options.flipbook_directory = Some(value.into()),
+            "--frames" => options.flipbook_frame_count = parse_value(arg, value)?,
+            "--export-mesh" => options.mesh_path = Some(value.into()),
+            "--mesh-time" => options.mesh_time_sec = parse_value(arg, value)?,
+            "--export-animation" => options.animation_path = Some(value.into()),
+            "--animation-frame-rate" => options.animation_frame_rate = parse_value(arg, value)?,
+            "--choppiness" => options.choppiness = parse_value(arg, value)?,
+            _ => return Err(format!("Unknown argument {}", arg)),
+         }
+      }
+      if options.water_depth <= 0.0 {
+         return Err(format!("Water depth {} is not positive", options.water_depth));
+      }
+      if !options.lattice_size.is_power_of_two() {
+         return Err(format!("Lattice size {} is not a power of 2", options.lattice_size));
+      }
+      if let Some(path) = &options.mesh_path {
+         if MeshFormat::from_path(path).is_none() {
+            return Err(format!("Unknown mesh format of {}", path.display()));
+         }
+      }
+      if let Some(path) = &options.animation_path {
+         if animation_format(path).is_none() {
+            return Err(format!("Unknown animation format of {}", path.display()));
+         }
+      }
+      Ok(options)
+   }
+}
+
+fn animation_format(path: &std::path::Path) -> Option<AnimationFormat> {
+   match path.extension()?.to_str()? {
+      "pc2" => Some(AnimationFormat::Pc2),
+      "glb" => Some(AnimationFormat::GltfMorphTargets),
+      _ => None,
+   }
+}
+
+fn parse_value<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+   value.parse::<T>()
+      .map_err(|_| format!("Invalid value {} of argument {}", value, arg))
+}
+
+fn make_height_field(options: &HeadlessOptions) -> HeightFieldCpu {
+   let wind = Wind::new(consts::WIND_VELOCITY,
+      glam::vec2(consts::WIND_DIRECTION_X, consts::WIND_DIRECTION_Y));
+   // JONSWAP is attenuated in finite depth water (TMA)
+   let spectrum_idx = if options.water_depth.is_finite() { 3 } else { 0 };
+   let spectrum = crate::make_wave_spectrum(spectrum_idx, consts::JONSWAP_PEAK_ENHANCEMENT,
+      consts::JONSWAP_FETCH_METERS / 1000.0, options.water_depth);
+   let spreading = crate::make_directional_spreading(0, consts::SPREADING_EXPONENT, 0.0);
+   let parameters = SpectrumParameters {
+      amplitude: consts::SPECTRUM_AMPLITUDE,
+      length_cutoff_meters: consts::WAVELENGTH_CUTOFF_METERS,
+      wind: &wind,
+      spectrum: spectrum.as_ref(),
+      spreading: spreading.as_ref(),
+      seed: options.seed,
+      wavenumber_band: (0.0, f32::INFINITY),
+      water_depth: options.water_depth,
+   };
+   HeightFieldCpu::new(options.lattice_size,
+      options.physical_size, options.period_sec, &parameters)
+}
+
+// Runs the simulation, every computed step is passed to the callback
+pub fn run(options: &HeadlessOptions, mut on_step: impl FnMut(usize, f32, &DisplacementGrid)) {
+   let height_field = make_height_field(options);
+   for step in 0..options.steps {
+      let time = step as f32 * options.time_step_sec;
+      let displacement = height_field.compute_height_field_cpu(time);
+      on_step(step, time, &displacement);
+   }
+}
+
+// Entry point of headless mode, logs progress of the simulation
+pub fn main(args: &[String]) {
+   let options = match HeadlessOptions::from_args(args) {
+      Ok(options) => options,
+      Err(message) => {
+         log::error!("{}\n{}", message, USAGE);
+         std::process::exit(1);
+      }
+   };
+   if options.heightmaps_directory.is_some() || options.flipbook_directory.is_some()
+      || options.mesh_path.is_some() || options.animation_path.is_some() {
+      export(&options);
+      return;
+   }
+   log::info!("Headless simulation: {} steps of {}x{} lattice",
+      options.steps, options.lattice_size, options.lattice_size);
+   run(&options, |step, time, displacement| {
+      let (min_height, max_height) = displacement.dy.iter()
+         .fold((f32::MAX, f32::MIN), |(min, max), &h| (min.min(h), max.max(h)));
+      log::info!("Step {}, time {:.3} sec: height from {:.2} to {:.2} m",
+         step, time, min_height, max_height);
+   });
+}
+
+// Export modes sample the simulation (over one period, or at given time),
+// instead of stepping
+fn export(options: &HeadlessOptions) {
+   let height_field = make_height_field(options);
+   let sample = |time| height_field.compute_height_field_cpu(time);
+   if let Some(directory) = &options.heightmaps_directory {
+      match crate::export::export_heightmaps(directory, &options.heightmaps,
+         options.period_sec, options.physical_size, sample) {
+         Ok(n_frames) => log::info!("Exported {} heightmaps to {}", n_frames, directory.display()),
+         Err(error) => {
+            log::error!("Failed to export heightmaps: {}", error);
+            std::process::exit(1);
+         }
+      }
+   }
+   if let Some(directory) = &options.flipbook_directory {
+      let flipbook = FlipbookExport {
+         frame_count: options.flipbook_frame_count,
+         choppiness: options.choppiness,
+      };
+      match crate::export::export_flipbook(directory, &flipbook,
+         options.period_sec, options.physical_size, sample) {
+         Ok(()) => log::info!("Exported flipbook to {}", directory.display()),
+         Err(error) => {
+            log::error!("Failed to export flipbook: {}", error);
+            std::process::exit(1);
+         }
+      }
+   }
+   if let Some(path) = &options.mesh_path {
+      let mesh = SurfaceMesh::from_displacement(&sample(options.mesh_time_sec),
+         options.physical_size, options.choppiness);
+      match mesh.write(path, MeshFormat::from_path(path).unwrap()) {
+         Ok(()) => log::info!("Exported mesh to {}", path.display()),
+         Err(error) => {
+            log::error!("Failed to export mesh: {}", error);
+            std::process::exit(1);
+         }
+      }
+   }
+   if let Some(path) = &options.animation_path {
+      let animation = AnimationExport {
+         format: animation_format(path).unwrap(),
+         frame_rate: options.animation_frame_rate,
+         choppiness: options.choppiness,
+      };
+      match crate::mesh_export::export_animation(path, &animation,
+         options.period_sec, options.physical_size, sample) {
+         Ok(n_frames) => log::info!("Exported {} frames of animation to {}", n_frames, path.display()),
+         Err(error) => {
+            log::error!("Failed to export animation: {}", error);
+            std::process::exit(1);
+         }
+      }
+   }
+}
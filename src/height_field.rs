@@ -1,15 +1,50 @@
 // HeightField - encapsulation of FFT-based algorithm for 
 // water height field generation at moment t
 
-use crate::{consts, make_compute_shader};
-use crate::wave::Wind;
-use glium::texture::{RawImage2d, Texture2dDataSource};
-use glium::{Display, Texture2d, uniforms, };
-use glium::uniforms::{Sampler};
+use crate::consts;
+use crate::shader::make_compute_shader;
+use crate::height_field_cpu::{Displacement, DisplacementGrid};
+use crate::readback::{AsyncReadback, ReadbackFrame};
+use crate::wave::{Wind, WaveSpectrum, DirectionalSpreading};
+use glium::{Display, Texture2d};
 use glium::GlObject;
 extern crate gl;
 
 type TextureResult<T> = Result<T, glium::texture::TextureCreationError>;
+
+// Source of HeightField::spectrum_id
+static NEXT_SPECTRUM_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+// Contents of RGBA 32-bit float texture, as it's stored in CPU memory
+pub type TextureData = Vec<Vec<(f32, f32, f32, f32)>>;
+
+// Everything the initial stationary spectrum depends on
+// (besides lattice size and physical size)
+pub struct SpectrumParameters<'a> {
+   pub amplitude: f32,
+   pub length_cutoff_meters: f32,
+   pub wind: &'a Wind,
+   pub spectrum: &'a dyn WaveSpectrum,
+   pub spreading: &'a dyn DirectionalSpreading,
+   pub seed: u64,
+   // only waves with wavevector magnitude in [min, max) are simulated
+   pub wavenumber_band: (f32, f32),
+   // meters, infinite for deep water
+   pub water_depth: f32,
+}
+
+// Parameters of the base spectrum, that a new height field starts with,
+// so that the spectrum is built only once
+#[derive(Copy, Clone)]
+pub struct SeaState {
+   pub wind: Wind,
+   pub spectrum_amplitude: f32,
+   pub length_cutoff_meters: f32,
+   pub water_depth: f32,
+   pub seed: u64,
+   pub wavenumber_band: (f32, f32),
+}
+
 pub struct HeightField {
    // size of computing domain on GPU
    // has to be a power of 2 (preferably below 2048)
@@ -19,12 +54,26 @@ pub struct HeightField {
    // waves with smaller length will be discarded (to improve convergence)
    length_cutoff_meters: f32,
 
+   // waves with wavevector magnitude outside of [min, max) will be discarded
+   // (e.g. when they are simulated by another height field of OceanCascades)
+   wavenumber_band: (f32, f32),
+
+   // meters, infinite for deep water, changes both the spectrum and
+   // how fast waves travel (dispersion relation)
+   water_depth: f32,
+
    // period of global ocean motion
    period_sec: f32,
 
    // waves perpendicular to wind will be suppressed
    wind: Wind,
 
+   // empirical model of energy distribution among waves of different frequencies
+   spectrum: Box<dyn WaveSpectrum>,
+
+   // empirical model of energy distribution among waves of different directions
+   spreading: Box<dyn DirectionalSpreading>,
+
    // 2D FFT twiddle indices (complex exponentials, that are independent of time)
    twiddle_indices: Option<Texture2d>,
 
@@ -34,6 +83,33 @@ pub struct HeightField {
    base_spectrum_minus_k: Option<Texture2d>,
    spectrum_amplitude: f32,
 
+   // when the base spectrum changes (not the random phases), the old one
+   // fades into the new one over transition_duration_sec, so that waves grow
+   // or calm down instead of popping
+   // both spectra are kept in CPU memory, to start a new transition in the
+   // middle of the current one
+   base_spectrum_cpu: (TextureData, TextureData),
+   previous_base_spectrum_cpu: Option<(TextureData, TextureData)>,
+   previous_base_spectrum: Option<Texture2d>,
+   previous_base_spectrum_minus_k: Option<Texture2d>,
+   transition_duration_sec: f32,
+   // clock of the render loop (not simulation time, which is anything for
+   // exports), when the current transition started (set on the first
+   // advance_spectrum_transition after the change), and the current blend factor
+   transition_start_time: Option<f32>,
+   spectrum_blend: f32,
+
+   // random phases and amplitudes of the base spectrum are
+   // reproducible for the same seed
+   seed: u64,
+
+   // expected variance of height, for the current base spectrum
+   height_variance: f32,
+
+   // different for every base spectrum of every height field
+   // (e.g. to know, whether statistics of the spectrum are outdated)
+   spectrum_id: u64,
+
    // spectrum at time t (defines displacement in all 3 dimensions)
    spectrum_realization_dx: Option<Texture2d>,
    spectrum_realization_dy: Option<Texture2d>,
@@ -47,47 +123,101 @@ pub struct HeightField {
    height_field_current: Option<Texture2d>,
    height_field_previous: Option<Texture2d>,
 
+   // result of inverse FFT of all 3 spectrum realizations
+   // (X - displacement x, Y - height, Z - displacement z)
+   displacement: Option<Texture2d>,
+
+   // derived from the displacement map, for lighting and foam
+   // normal map - unit normals of displaced surface (XYZ)
+   // jacobian map - determinant of horizontal displacement Jacobian (RGB),
+   // where it's below zero, the surface folds over itself
+   normal_map: Option<Texture2d>,
+   jacobian_map: Option<Texture2d>,
+
+   // horizontal displacement multiplier, should match the one used for rendering
+   choppiness: f32,
+
+   // displacement map copied to CPU memory for sampling at some moment,
+   // reused while the simulated time stays the same
+   sampled_displacement: std::cell::RefCell<Option<(f32, DisplacementGrid)>>,
+
+   // non-blocking copy of the displacement map to CPU memory,
+   // created on the first request
+   async_readback: std::cell::RefCell<Option<AsyncReadback>>,
+   readback_buffer_count: usize,
+
    // OpenGL GPU program to combine precomputed maps, and 
    // find height field for the current frame
    fft_compute_shader: glium::program::ComputeShader,
+
+   // OpenGL GPU programs to compute one stage of inverse FFT, and
+   // to write the final real-valued result into the displacement map
+   butterfly_compute_shader: glium::program::ComputeShader,
+   displacement_compute_shader: glium::program::ComputeShader,
+
+   // OpenGL GPU program to find normals and Jacobian of the displaced surface
+   surface_compute_shader: glium::program::ComputeShader,
 }
 
 impl HeightField {
-   pub fn new(display: &Display, lattice_size: usize, physical_size: f32, period_sec: f32) -> Self {
+   pub fn new(display: &Display, lattice_size: usize, physical_size: f32, period_sec: f32,
+      sea_state: SeaState, spectrum: Box<dyn WaveSpectrum>, spreading: Box<dyn DirectionalSpreading>) -> Self {
       let mut instance = Self {
          size: lattice_size,
          physical_size,
-         length_cutoff_meters: consts::WAVELENGTH_CUTOFF_METERS,
-         spectrum_amplitude: consts::PHILLIPS_SPECTRUM_AMPLITUDE,
+         length_cutoff_meters: sea_state.length_cutoff_meters,
+         wavenumber_band: sea_state.wavenumber_band,
+         water_depth: sea_state.water_depth,
+         spectrum_amplitude: sea_state.spectrum_amplitude,
+         seed: sea_state.seed,
+         height_variance: 0.0,
+         spectrum_id: 0,
          period_sec,
-         wind: Wind::new(consts::WIND_VELOCITY,
-            glam::vec2(consts::WIND_DIRECTION_X, consts::WIND_DIRECTION_Y)),
+         wind: sea_state.wind,
+         spectrum,
+         spreading,
          twiddle_indices: None,
          base_spectrum: None,
          base_spectrum_minus_k: None,
+         base_spectrum_cpu: (Vec::new(), Vec::new()),
+         previous_base_spectrum_cpu: None,
+         previous_base_spectrum: None,
+         previous_base_spectrum_minus_k: None,
+         transition_duration_sec: 0.0,
+         transition_start_time: None,
+         spectrum_blend: 1.0,
          spectrum_realization_dx: None,
          spectrum_realization_dy: None,
          spectrum_realization_dz: None,
          height_field_current: None,
          height_field_previous: None,
+         displacement: None,
+         normal_map: None,
+         jacobian_map: None,
+         choppiness: consts::CHOPPINESS,
+         sampled_displacement: std::cell::RefCell::new(None),
+         async_readback: std::cell::RefCell::new(None),
+         readback_buffer_count: consts::READBACK_BUFFER_COUNT,
          fft_compute_shader: make_compute_shader!(display, "shaders/fft.comp"),
+         butterfly_compute_shader: make_compute_shader!(display, "shaders/butterfly.comp"),
+         displacement_compute_shader: make_compute_shader!(display, "shaders/displacement.comp"),
+         surface_compute_shader: make_compute_shader!(display, "shaders/surface.comp"),
       };
-      instance.regenerate_textures(display, lattice_size, physical_size);
+      instance.regenerate_textures(display, lattice_size, physical_size, sea_state.wavenumber_band);
       instance
    }
 
-   pub fn regenerate_textures(&mut self, display: &Display, size: usize, physical_size: f32) {
+   // The wavenumber band usually changes together with the sizes (see OceanCascades)
+   pub fn regenerate_textures(&mut self, display: &Display, size: usize, physical_size: f32,
+      wavenumber_band: (f32, f32)) {
       self.size = size;
       self.physical_size = physical_size;
+      self.wavenumber_band = wavenumber_band;
+      self.async_readback.replace(None);
       let twiddle_indices = Self::make_twiddle_indices(display, self.size)
          .expect("Couldn't generate texture for FFT twiddle indices");
       self.twiddle_indices = twiddle_indices.into();
-      let (base_spectrum, base_spectrum_minus_k) = Self::make_base_spectrum(
-         display, self.size, physical_size, self.spectrum_amplitude,
-         self.length_cutoff_meters, &self.wind)
-         .expect("Couldn't generate two textures of FFT base spectum");
-      self.base_spectrum = Some(base_spectrum);
-      self.base_spectrum_minus_k = Some(base_spectrum_minus_k);
+      self.regenerate_base_spectrum(display);
 
       let (spectrum_dx, spectrum_dy, spectrum_dz) = Self::make_spectrum_realizations(display, self.size)
          .expect("Couldn't generate three textures of spectrum time realization");
@@ -96,88 +226,308 @@ impl HeightField {
       self.spectrum_realization_dy = Some(spectrum_dy);
       self.spectrum_realization_dz = Some(spectrum_dz);
 
-      // let noise_map = Self::make_noise_map(display, self.size)
-      //    .expect("Couldn't generate texture with Standard Normal random values");
-      // self.noise_map = Some(noise_map);
-
       let (field_current, field_previous) = Self::make_height_field(display, self.size)
       .expect("Couldn't generate empty textures for height field");
       self.height_field_current = Some(field_current);
       self.height_field_previous = Some(field_previous);
+
+      let displacement = Self::make_displacement_map(display, self.size)
+         .expect("Couldn't generate empty texture for displacement map");
+      self.displacement = Some(displacement);
+
+      let normal_map = Self::make_displacement_map(display, self.size)
+         .expect("Couldn't generate empty texture for normal map");
+      self.normal_map = Some(normal_map);
+      let jacobian_map = Self::make_displacement_map(display, self.size)
+         .expect("Couldn't generate empty texture for Jacobian map");
+      self.jacobian_map = Some(jacobian_map);
+   }
+
+   // Only the stationary spectrum depends on wind and spectrum model
+   // (instantly, any transition is dropped)
+   fn regenerate_base_spectrum(&mut self, display: &Display) {
+      self.drop_previous_base_spectrum();
+      self.update_base_spectrum(display);
+   }
+
+   fn drop_previous_base_spectrum(&mut self) {
+      self.previous_base_spectrum_cpu = None;
+      self.previous_base_spectrum = None;
+      self.previous_base_spectrum_minus_k = None;
+      self.transition_start_time = None;
+      self.spectrum_blend = 1.0;
+   }
+
+   // The spectrum, that is currently visible, becomes the previous one
+   // (mid-transition it's a mix of two spectra), and it fades into the new one
+   fn fade_to_new_base_spectrum(&mut self, display: &Display) {
+      if self.transition_duration_sec <= 0.0 || self.base_spectrum_cpu.0.is_empty() {
+         self.regenerate_base_spectrum(display);
+         return;
+      }
+      let blend = self.spectrum_blend;
+      let current = std::mem::take(&mut self.base_spectrum_cpu);
+      let previous = match self.previous_base_spectrum_cpu.take() {
+         Some(previous) if blend < 1.0 => (
+            Self::mix_texture_data(&previous.0, &current.0, blend),
+            Self::mix_texture_data(&previous.1, &current.1, blend)),
+         _ => current,
+      };
+      self.previous_base_spectrum = Some(Self::make_spectrum_texture(display, previous.0.clone())
+         .expect("Couldn't generate texture of previous FFT base spectrum"));
+      self.previous_base_spectrum_minus_k = Some(Self::make_spectrum_texture(display, previous.1.clone())
+         .expect("Couldn't generate texture of previous FFT base spectrum"));
+      self.previous_base_spectrum_cpu = Some(previous);
+      self.transition_start_time = None;
+      self.spectrum_blend = 0.0;
+      self.update_base_spectrum(display);
+   }
+
+   fn update_base_spectrum(&mut self, display: &Display) {
+      self.base_spectrum_cpu = Self::make_base_spectrum_cpu(
+         self.size, self.physical_size, &self.spectrum_parameters());
+      self.base_spectrum = Some(Self::make_spectrum_texture(display, self.base_spectrum_cpu.0.clone())
+         .expect("Couldn't generate texture of FFT base spectum"));
+      self.base_spectrum_minus_k = Some(Self::make_spectrum_texture(display, self.base_spectrum_cpu.1.clone())
+         .expect("Couldn't generate texture of FFT base spectum"));
+      self.height_variance = Self::height_variance(
+         self.size, self.physical_size, &self.spectrum_parameters());
+      self.spectrum_id = NEXT_SPECTRUM_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+      self.sampled_displacement.replace(None);
    }
 
    // Launch "fft.comp" GPU program, pass input data 
    // (input textures with precomputed stationary spectrum,
    //  output textures for spectrum realization)
+   // Then transform every spectrum realization into spatial domain
+   // with inverse FFT, results are gathered in the displacement map,
+   // from which normal and Jacobian maps are derived
    pub fn compute_height_field_gpu(&self, time: f32) {
       unsafe {
          if let glium::Handle::Id(id) = self.fft_compute_shader.get_id() {
             gl::UseProgram(id);
 
-            let location = gl::GetUniformLocation(id, "o_hkt_dx".as_ptr() as *const i8);
-            // assert!(location != -1);
-            gl::Uniform1i(location, 0);
-            gl::BindImageTexture(0, self.spectrum_realization_dx.as_ref().unwrap().get_id(),
-               0, gl::FALSE, 0, gl::WRITE_ONLY, gl::RGBA32F);
-
-            let location = gl::GetUniformLocation(id, "o_hkt_dy".as_ptr() as *const i8);
-            // assert!(location != -1);
-            gl::Uniform1i(location, 1);
-            gl::BindImageTexture(1, self.spectrum_realization_dy.as_ref().unwrap().get_id(),
-               0, gl::FALSE, 0, gl::WRITE_ONLY, gl::RGBA32F);
-
-            let location = gl::GetUniformLocation(id, "o_hkt_dz".as_ptr() as *const i8);
-            // assert!(location != -1);
-            gl::Uniform1i(location, 2);
-            gl::BindImageTexture(2, self.spectrum_realization_dz.as_ref().unwrap().get_id(),
-               0, gl::FALSE, 0, gl::WRITE_ONLY, gl::RGBA32F);
-
-            let location = gl::GetUniformLocation(id, "i_h0k".as_ptr() as *const i8);
-            // assert!(location != -1);
-            gl::Uniform1i(location, 3);
-            gl::BindImageTexture(3, self.base_spectrum.as_ref().unwrap().get_id(),
-               0, gl::FALSE, 0, gl::READ_ONLY, gl::RGBA32F);
-
-            let location = gl::GetUniformLocation(id, "i_h0_minus_k".as_ptr() as *const i8);
-            // assert!(location != -1);
-            gl::Uniform1i(location, 4);
-            gl::BindImageTexture(4, self.base_spectrum_minus_k.as_ref().unwrap().get_id(),
-               0, gl::FALSE, 0, gl::READ_ONLY, gl::RGBA32F);
-
-            let location = gl::GetUniformLocation(id, "u_PhysicalSize".as_ptr() as *const i8);
-            // assert!(location != -1);
-            gl::Uniform1f(location, self.physical_size);
-
-            let location = gl::GetUniformLocation(id, "u_BaseFrequency".as_ptr() as *const i8);
-            // log::info!("loc {}", location);
-            // assert!(location != -1);
-            gl::Uniform1f(location, self.base_frequency());
-
-            let location = gl::GetUniformLocation(id, "u_Time".as_ptr() as *const i8);
-            // log::info!("loc {} {} ", self.base_frequency(), self.physical_size);
-            // assert!(location != -1);
-            gl::Uniform1f(location, time);
+            Self::bind_image(0, self.spectrum_realization_dx.as_ref().unwrap(), gl::WRITE_ONLY);
+            Self::bind_image(1, self.spectrum_realization_dy.as_ref().unwrap(), gl::WRITE_ONLY);
+            Self::bind_image(2, self.spectrum_realization_dz.as_ref().unwrap(), gl::WRITE_ONLY);
+            Self::bind_image(3, self.base_spectrum.as_ref().unwrap(), gl::READ_ONLY);
+            Self::bind_image(4, self.base_spectrum_minus_k.as_ref().unwrap(), gl::READ_ONLY);
+            // without transition, the current spectrum is blended with itself
+            Self::bind_image(5, self.previous_base_spectrum.as_ref()
+               .or(self.base_spectrum.as_ref()).unwrap(), gl::READ_ONLY);
+            Self::bind_image(6, self.previous_base_spectrum_minus_k.as_ref()
+               .or(self.base_spectrum_minus_k.as_ref()).unwrap(), gl::READ_ONLY);
+            gl::Uniform1f(Self::uniform_location(id, "u_SpectrumBlend"), self.spectrum_blend);
+
+            gl::Uniform1f(Self::uniform_location(id, "u_PhysicalSize"), self.physical_size);
+            gl::Uniform1f(Self::uniform_location(id, "u_BaseFrequency"), self.base_frequency());
+            gl::Uniform1f(Self::uniform_location(id, "u_Time"), time);
+            // zero for deep water
+            gl::Uniform1f(Self::uniform_location(id, "u_WaterDepth"),
+               if self.water_depth.is_finite() { self.water_depth } else { 0.0 });
 
             gl::DispatchCompute((self.size / 8) as u32, (self.size / 8) as u32, 1);
-            gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT);
+            gl::MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
 
             gl::UseProgram(0);
          }
       }
-      // self.fft_compute_shader.execute(glium::uniform!{
-      //    // o_hkt_dx: self.spectrum_realization_dx.as_ref().unwrap(),
-      //    // o_hkt_dy: self.spectrum_realization_dy.as_ref().unwrap()),
-      //    // o_hkt_dz: self.spectrum_realization_dz.as_ref().unwrap()),
-      //    // i_h0k : self.base_spectrum.as_ref().unwrap()),
-      //    // i_h0_minus_k : self.base_spectrum_minus_k.as_ref().unwrap()),
-      //    u_PhysicalSize: self.physical_size,
-      //    u_BaseFrequency: self.base_frequency(),
-      //    u_Time: time,
-      // }, (self.size / 16) as u32, (self.size / 16) as u32, 1);
+
+      let spectrum_realizations = [
+         self.spectrum_realization_dx.as_ref().unwrap(),
+         self.spectrum_realization_dy.as_ref().unwrap(),
+         self.spectrum_realization_dz.as_ref().unwrap(),
+      ];
+      for (component, spectrum_realization) in spectrum_realizations.iter().enumerate() {
+         let spatial = self.compute_inverse_fft_gpu(spectrum_realization);
+         self.write_displacement_gpu(spatial, component);
+      }
+      self.write_surface_gpu();
+
+      unsafe {
+         // make results visible for rendering, and for copies to CPU memory
+         // (glGetTextureImage of AsyncReadback)
+         gl::MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT | gl::FRAMEBUFFER_BARRIER_BIT
+            | gl::TEXTURE_UPDATE_BARRIER_BIT);
+      }
+   }
+
+   // Launch "butterfly.comp" GPU program 2*logN times (first horizontal stages, then vertical),
+   // reading the input spectrum only on the first stage and "ping-ponging" 
+   // between the two height field textures afterwards
+   // Returns the texture containing the last stage result
+   fn compute_inverse_fft_gpu<'a>(&'a self, spectrum: &'a Texture2d) -> &'a Texture2d {
+      let n_stages = usize::trailing_zeros(self.size) as i32; // == log2(size)
+      let mut input = spectrum;
+      let mut output = self.height_field_current.as_ref().unwrap();
+      let mut other = self.height_field_previous.as_ref().unwrap();
+      unsafe {
+         if let glium::Handle::Id(id) = self.butterfly_compute_shader.get_id() {
+            gl::UseProgram(id);
+            Self::bind_image(0, self.twiddle_indices.as_ref().unwrap(), gl::READ_ONLY);
+            let stage_location = Self::uniform_location(id, "u_Stage");
+            let direction_location = Self::uniform_location(id, "u_Direction");
+            for direction in 0..2 {
+               gl::Uniform1i(direction_location, direction);
+               for stage in 0..n_stages {
+                  gl::Uniform1i(stage_location, stage);
+                  Self::bind_image(1, input, gl::READ_ONLY);
+                  Self::bind_image(2, output, gl::WRITE_ONLY);
+                  gl::DispatchCompute((self.size / 8) as u32, (self.size / 8) as u32, 1);
+                  gl::MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+
+                  input = output;
+                  std::mem::swap(&mut output, &mut other);
+               }
+            }
+            gl::UseProgram(0);
+         }
+      }
+      input
+   }
+
+   // Launch "displacement.comp" GPU program, to fix signs of inverse FFT result
+   // and put it into one channel of the displacement map
+   fn write_displacement_gpu(&self, spatial: &Texture2d, component: usize) {
+      unsafe {
+         if let glium::Handle::Id(id) = self.displacement_compute_shader.get_id() {
+            gl::UseProgram(id);
+            Self::bind_image(0, spatial, gl::READ_ONLY);
+            Self::bind_image(1, self.displacement.as_ref().unwrap(), gl::READ_WRITE);
+            gl::Uniform1i(Self::uniform_location(id, "u_Component"), component as i32);
+            gl::DispatchCompute((self.size / 8) as u32, (self.size / 8) as u32, 1);
+            gl::MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+            gl::UseProgram(0);
+         }
+      }
+   }
+
+   // Launch "surface.comp" GPU program, to fill normal and Jacobian maps
+   // from finite differences of the displacement map
+   fn write_surface_gpu(&self) {
+      unsafe {
+         if let glium::Handle::Id(id) = self.surface_compute_shader.get_id() {
+            gl::UseProgram(id);
+            Self::bind_image(0, self.displacement.as_ref().unwrap(), gl::READ_ONLY);
+            Self::bind_image(1, self.normal_map.as_ref().unwrap(), gl::WRITE_ONLY);
+            Self::bind_image(2, self.jacobian_map.as_ref().unwrap(), gl::WRITE_ONLY);
+            gl::Uniform1f(Self::uniform_location(id, "u_PhysicalSize"), self.physical_size);
+            gl::Uniform1f(Self::uniform_location(id, "u_Choppiness"), self.choppiness);
+            gl::DispatchCompute((self.size / 8) as u32, (self.size / 8) as u32, 1);
+            gl::MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+            gl::UseProgram(0);
+         }
+      }
+   }
+
+   // All textures of the height field are RGBA 32-bit float
+   unsafe fn bind_image(unit: u32, texture: &Texture2d, access: gl::types::GLenum) {
+      gl::BindImageTexture(unit, texture.get_id(), 0, gl::FALSE, 0, access, gl::RGBA32F);
+   }
+
+   // OpenGL expects uniform names as null-terminated strings
+   unsafe fn uniform_location(program: gl::types::GLuint, name: &str) -> gl::types::GLint {
+      let name = std::ffi::CString::new(name).unwrap();
+      gl::GetUniformLocation(program, name.as_ptr())
    }
 
    pub fn set_period(&mut self, period_sec: f32) {
-      self.period_sec = period_sec
+      self.period_sec = period_sec;
+      self.sampled_displacement.replace(None);
+   }
+
+   pub fn set_choppiness(&mut self, choppiness: f32) {
+      self.choppiness = choppiness
+   }
+
+   // Setters below change only the stationary spectrum, so lattice-dependent
+   // textures (twiddle indices, spectrum realizations) are kept
+   // Changes of the sea state are faded in (see set_transition_duration),
+   // while a new seed resets the waves
+   pub fn set_spectrum(&mut self, display: &Display, spectrum: Box<dyn WaveSpectrum>) {
+      self.spectrum = spectrum;
+      self.fade_to_new_base_spectrum(display);
+   }
+
+   pub fn set_spreading(&mut self, display: &Display, spreading: Box<dyn DirectionalSpreading>) {
+      self.spreading = spreading;
+      self.fade_to_new_base_spectrum(display);
+   }
+
+   pub fn set_seed(&mut self, display: &Display, seed: u64) {
+      self.seed = seed;
+      self.regenerate_base_spectrum(display);
+   }
+
+   pub fn set_wind(&mut self, display: &Display, wind: Wind) {
+      self.wind = wind;
+      self.fade_to_new_base_spectrum(display);
+   }
+
+   pub fn set_spectrum_amplitude(&mut self, display: &Display, amplitude: f32) {
+      self.spectrum_amplitude = amplitude;
+      self.fade_to_new_base_spectrum(display);
+   }
+
+   pub fn set_length_cutoff(&mut self, display: &Display, length_cutoff_meters: f32) {
+      self.length_cutoff_meters = length_cutoff_meters;
+      self.fade_to_new_base_spectrum(display);
+   }
+
+   // Zero makes changes instant
+   pub fn set_transition_duration(&mut self, duration_sec: f32) {
+      self.transition_duration_sec = duration_sec.max(0.0);
+   }
+
+   // From 0 (the previous spectrum) to 1 (the current one)
+   pub fn spectrum_blend(&self) -> f32 {
+      self.spectrum_blend
+   }
+
+   // Called once per frame with the clock of the render loop, computes in
+   // between use the same blend, whatever time they are for
+   // The previous spectrum is released, once the transition is over
+   pub fn advance_spectrum_transition(&mut self, clock_sec: f32) {
+      if self.previous_base_spectrum.is_none() {
+         return;
+      }
+      let start_time = *self.transition_start_time.get_or_insert(clock_sec);
+      self.spectrum_blend = ((clock_sec - start_time) / self.transition_duration_sec.max(f32::EPSILON)).clamp(0.0, 1.0);
+      self.sampled_displacement.replace(None);
+      if self.spectrum_blend >= 1.0 {
+         self.drop_previous_base_spectrum();
+      }
+   }
+
+   // Infinite depth for deep water
+   pub fn set_water_depth(&mut self, display: &Display, water_depth: f32) {
+      self.water_depth = water_depth;
+      self.fade_to_new_base_spectrum(display);
+   }
+
+   pub fn size(&self) -> usize {
+      self.size
+   }
+
+   pub fn physical_size(&self) -> f32 {
+      self.physical_size
+   }
+
+   pub fn period(&self) -> f32 {
+      self.period_sec
+   }
+
+   pub fn spectrum_parameters(&self) -> SpectrumParameters<'_> {
+      SpectrumParameters {
+         amplitude: self.spectrum_amplitude,
+         length_cutoff_meters: self.length_cutoff_meters,
+         wind: &self.wind,
+         spectrum: self.spectrum.as_ref(),
+         spreading: self.spreading.as_ref(),
+         seed: self.seed,
+         wavenumber_band: self.wavenumber_band,
+         water_depth: self.water_depth,
+      }
    }
 
    pub fn twiddle_indices_texture(&self) -> Option<&glium::Texture2d> {
@@ -200,6 +550,71 @@ impl HeightField {
       self.height_field_previous.as_ref()
    }
 
+   pub fn displacement_map(&self) -> Option<&glium::Texture2d> {
+      self.displacement.as_ref()
+   }
+
+   pub fn normal_map(&self) -> Option<&glium::Texture2d> {
+      self.normal_map.as_ref()
+   }
+
+   pub fn jacobian_map(&self) -> Option<&glium::Texture2d> {
+      self.jacobian_map.as_ref()
+   }
+
+   // Copy textures from GPU to CPU memory (blocks until GPU finishes computing)
+   pub fn read_displacement(&self) -> DisplacementGrid {
+      DisplacementGrid::from_texture_data(self.size,
+         &Self::read_texture(self.displacement.as_ref().unwrap()))
+   }
+
+   // Displacement of water surface, that is right at the given point at given time
+   // (horizontal coordinates of the grid, the height field repeats every physical_size
+   // meters, starting from the origin)
+   // Displacement map is computed and copied to CPU once for every new time,
+   // which stalls the GPU pipeline, so it's better to sample many points at once
+   pub fn sample(&self, grid_xz: glam::Vec2, time: f32) -> Displacement {
+      let mut sampled = self.sampled_displacement.borrow_mut();
+      if !matches!(sampled.as_ref(), Some((sampled_time, _)) if *sampled_time == time) {
+         self.compute_height_field_gpu(time);
+         *sampled = Some((time, self.read_displacement()));
+      }
+      let (_, grid) = sampled.as_ref().unwrap();
+      grid.sample(grid_xz, self.physical_size, self.choppiness)
+   }
+
+   // Start copying the displacement map (computed for the given time) to CPU
+   // memory, the copy becomes available a few frames later via latest_displacement()
+   // Should be called after compute_height_field_gpu(), once per frame
+   pub fn request_displacement_readback(&self, time: f32) {
+      self.async_readback.borrow_mut()
+         .get_or_insert_with(|| AsyncReadback::new(self.size, self.readback_buffer_count))
+         .request(self.displacement.as_ref().unwrap(), time);
+   }
+
+   // The most recent displacement grid copied without blocking (if any)
+   pub fn latest_displacement(&self) -> Option<std::cell::Ref<'_, ReadbackFrame>> {
+      std::cell::Ref::filter_map(self.async_readback.borrow(),
+         |readback| readback.as_ref().and_then(AsyncReadback::latest)).ok()
+   }
+
+   pub fn readback_latency_frames(&self) -> Option<u64> {
+      self.async_readback.borrow().as_ref().and_then(AsyncReadback::latency_frames)
+   }
+
+   // 2 for double buffering, 3 for triple buffering, etc.
+   // More buffers make stalls less likely, but increase latency
+   pub fn set_readback_buffer_count(&mut self, buffer_count: usize) {
+      self.readback_buffer_count = buffer_count;
+      self.async_readback.replace(None);
+   }
+
+   fn read_texture(texture: &Texture2d) -> TextureData {
+      let rect = glium::Rect { left: 0, bottom: 0, width: texture.width(), height: texture.height() };
+      texture.main_level().first_layer().into_image(None).unwrap()
+         .raw_read::<TextureData, (f32, f32, f32, f32)>(&rect)
+   }
+
    pub fn spectrum_realization_dx(&self) -> Option<&glium::Texture2d> {
       self.spectrum_realization_dx.as_ref()
    }
@@ -212,6 +627,19 @@ impl HeightField {
       self.spectrum_realization_dz.as_ref()
    }
 
+   // Average height of the highest third of waves (4 standard deviations of height)
+   pub fn significant_wave_height(&self) -> f32 {
+      4.0 * f32::sqrt(self.height_variance)
+   }
+
+   pub fn expected_height_variance(&self) -> f32 {
+      self.height_variance
+   }
+
+   pub fn spectrum_id(&self) -> u64 {
+      self.spectrum_id
+   }
+
    // To make simulation periodic, we need to make all subwaves frequencies
    // to be a multiple of some base frequency
    pub fn base_frequency(&self) -> f32 {
@@ -225,35 +653,44 @@ impl HeightField {
    // Red+Green channels  - twiddle factors
    // Blue+Alpha channels - indices
    fn make_twiddle_indices(display: &Display, size: usize) -> TextureResult<Texture2d> {
+      glium::Texture2d::with_format(display,
+         Self::make_twiddle_indices_cpu(size),
+         glium::texture::UncompressedFloatFormat::F32F32F32F32,
+         glium::texture::MipmapsOption::NoMipmap)
+   }
+
+   // The same twiddle factors and indices, kept in CPU memory
+   // Each row corresponds to an input of butterfly algorithm, each column to a stage
+   pub fn make_twiddle_indices_cpu(size: usize) -> TextureData {
       let n_cols = usize::trailing_zeros(size) as usize; // == log2(size)
       let mut reorder = vec![0; size];
       for col in 0..n_cols {
          let summand = size >> (col+1);
          let checker = 1 << col;
-         for row in 0..size {
+         for (row, reordered) in reorder.iter_mut().enumerate() {
             if (row / checker) % 2 == 1 {
-               reorder[row] += summand;
+               *reordered += summand;
             }
          }
       }
       // log::info!("{:?}", reorder);
       let mut twiddle_indices_cpu = vec![vec![(0.0f32, 0.00f32, 0.00f32, 0.00f32); n_cols]; size];
       let coef = 2.0 * consts::PI * glam::vec2(0.0, 1.0) / (size as f32);
-      for row in 0..size {
+      for (row, twiddle_indices_row) in twiddle_indices_cpu.iter_mut().enumerate() {
          // first column has reversed order of inputs
          {
-            let (index, other_index) =
-               if row % 2 == 1 {(reorder[row-1], reorder[row])}
-               else {(reorder[row], reorder[row+1])};
-            twiddle_indices_cpu[row][0] =
-               (1.0, 0.0, index as f32, other_index as f32);
+            let (twiddle, index, other_index) =
+               if row % 2 == 1 {(-1.0, reorder[row-1], reorder[row])}
+               else {(1.0, reorder[row], reorder[row+1])};
+            twiddle_indices_row[0] =
+               (twiddle, 0.0, index as f32, other_index as f32);
          }
-         for col in 1..n_cols {
+         for (col, twiddle_indices_entry) in twiddle_indices_row.iter_mut().enumerate().skip(1) {
             let two2col = 1 << (col);
             let b = size >> (col + 1);
             let k = (row * b) % size;
             //log::info!("{}", k);
-            let mut twiddle = crate::complex::complex_exp(coef * (k as f32));
+            let twiddle = crate::complex::complex_exp(coef * (k as f32));
             let is_bottom_wing = (row % (two2col * 2)) >= two2col;
             let (index, other_index) = if is_bottom_wing {
                // twiddle = -twiddle;
@@ -261,14 +698,11 @@ impl HeightField {
             } else {
                (row, row + two2col)
             };
-            twiddle_indices_cpu[row][col] =
+            *twiddle_indices_entry =
                   (twiddle.x, twiddle.y, index as f32, other_index as f32);
          }
       }
-      glium::Texture2d::with_format(display,
-         twiddle_indices_cpu,
-         glium::texture::UncompressedFloatFormat::F32F32F32F32,
-         glium::texture::MipmapsOption::NoMipmap)
+      twiddle_indices_cpu
    }
 
    // Initial Fourier components \hat{h}(k) and conjugate \hat{h}^*(-k)
@@ -276,84 +710,114 @@ impl HeightField {
    // Since we compute height field on GPU via OpenGL,
    // those should be stored in way accessible by OpenGL.
    // The easiest - is a 2D texture, for each component
-   fn make_base_spectrum(display: &glium::Display, size: usize, physical_size: f32, spectrum_amplitude: f32, wave_cutoff: f32, wind: &Wind) -> TextureResult<(Texture2d, Texture2d)> {
-      let mut spectrum_cpu = vec![vec![(0.00f32, 0.00f32, 0.00f32, 0.00f32); size]; size];
-      let mut spectrum_conjugate_cpu = vec![vec![(0.00f32, 0.00f32, 0.00f32, 0.00f32,); size]; size];
+   fn make_spectrum_texture(display: &glium::Display, data: TextureData) -> TextureResult<Texture2d> {
+      glium::Texture2d::with_format(display,
+         data,
+         glium::texture::UncompressedFloatFormat::F32F32F32F32,
+         glium::texture::MipmapsOption::NoMipmap)
+   }
 
-      use rand::Rng;
-      let mut rng = rand::thread_rng();
+   fn mix_texture_data(from: &TextureData, to: &TextureData, blend: f32) -> TextureData {
+      let mix = |a: f32, b: f32| a + (b - a) * blend;
+      from.iter().zip(to.iter()).map(|(from_row, to_row)|
+         from_row.iter().zip(to_row.iter()).map(|(a, b)|
+            (mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2), mix(a.3, b.3))).collect()
+      ).collect()
+   }
+   // The same initial Fourier components, kept in CPU memory
+   // Only h0(k) is random, h0(-k) is looked up at the mirrored wavevector, then
+   // h(-k,t) = h^*(k,t) for the realization at any t, so inverse FFT gives real values
+   pub fn make_base_spectrum_cpu(size: usize, physical_size: f32, parameters: &SpectrumParameters) -> (TextureData, TextureData) {
+      let mut spectrum_cpu = vec![vec![(0.00f32, 0.00f32, 0.00f32, 0.00f32); size]; size];
 
-      let inv_sqrt2 = 1.0 / f32::sqrt(2.0);
-      for row in 0..size {
-         for col in 0..size {
-            let k = crate::wave::wavevector_from_coords((row, col), size, physical_size);
-            let phillips_sqrt = f32::sqrt(
-               Self::phillips_spectrum(spectrum_amplitude, k, wave_cutoff, wind));
-            {
-               let rnd_real : f32 = rng.sample(rand_distr::StandardNormal);
-               let rnd_imag : f32 = rng.sample(rand_distr::StandardNormal);
-               let entry = inv_sqrt2 * glam::vec2(rnd_real, rnd_imag) * phillips_sqrt;
-               let entry = entry.clamp(glam::vec2(0.0, 0.0), glam::vec2(1000000.0, 1000000.0));
-               spectrum_cpu[row][col] = (entry.x, entry.y, 0.0, 1.0);
-            }
-            {
-               let rnd_real : f32 = rng.sample(rand_distr::StandardNormal);
-               let rnd_imag : f32 = rng.sample(rand_distr::StandardNormal);
-               let entry = inv_sqrt2 * glam::vec2(rnd_real, rnd_imag) * phillips_sqrt;
-               let entry = entry.clamp(glam::vec2(0.0, 0.0), glam::vec2(1000000.0, 1000000.0));
-               spectrum_conjugate_cpu[row][col] = (entry.x, entry.y, 0.0, 1.0);
+      use rand::{Rng, SeedableRng};
+      let mut rng = rand::rngs::StdRng::seed_from_u64(parameters.seed);
+
+      for (row, spectrum_row) in spectrum_cpu.iter_mut().enumerate() {
+         for (col, spectrum_entry) in spectrum_row.iter_mut().enumerate() {
+            // random values are drawn for every texel, so that the same seed
+            // gives the same waves regardless of spectrum parameters
+            let rnd_real : f32 = rng.sample(rand_distr::StandardNormal);
+            let rnd_imag : f32 = rng.sample(rand_distr::StandardNormal);
+            if Self::is_nyquist_frequency((row, col)) {
+               continue;
             }
+            // E|h0(k)|^2 = 0.5 * spectrum * dk^2, because both h0(k) and h0(-k)
+            // contribute to the same wave, so that variance of height equals
+            // integral of the spectrum
+            let entry = 0.5 * glam::vec2(rnd_real, rnd_imag)
+               * f32::sqrt(Self::wave_energy((row, col), size, physical_size, parameters));
+            *spectrum_entry = (entry.x, entry.y, 0.0, 1.0);
          }
       }
-      let spectrum = glium::Texture2d::with_format(display,
-         spectrum_cpu,
-         glium::texture::UncompressedFloatFormat::F32F32F32F32,
-         glium::texture::MipmapsOption::NoMipmap);
 
-      let spectrum_conjugate = glium::Texture2d::with_format(display,
-         spectrum_conjugate_cpu,
-         glium::texture::UncompressedFloatFormat::F32F32F32F32,
-         glium::texture::MipmapsOption::NoMipmap);
-
-      spectrum.and_then(|spectrum|
-         spectrum_conjugate.and_then(|spectrum_conjugate|
-            Ok((spectrum, spectrum_conjugate))))
-   }
-
-   // Not used now, but can be used for precomputing the data on GPU
-   fn make_noise_map(display: &Display, size: usize) -> TextureResult<Texture2d> {
-      let mut rng = rand::thread_rng();
-      use rand::prelude::*;
-      let noise_map_cpu = (0..size).map(|_|
-         (0..size).map(|_|
-            (rng.sample(rand_distr::StandardNormal),
-             rng.sample(rand_distr::StandardNormal),
-             rng.sample(rand_distr::StandardNormal),
-             rng.sample(rand_distr::StandardNormal))
+      let spectrum_conjugate_cpu = (0..size).map(|row|
+         (0..size).map(|col|
+            spectrum_cpu[(size - row) % size][(size - col) % size]
          ).collect::<Vec<(f32, f32, f32, f32)>>()
       ).collect::<Vec<_>>();
+      (spectrum_cpu, spectrum_conjugate_cpu)
+   }
 
-      glium::Texture2d::with_format(display,
-         noise_map_cpu,
-         glium::texture::UncompressedFloatFormat::F32F32F32F32,
-         glium::texture::MipmapsOption::NoMipmap)
+   // Spectrum integrated over the lattice cell of wavevector at given coordinates,
+   // i.e. expected squared height of this wave
+   pub fn wave_energy(coords: (usize, usize), size: usize, physical_size: f32, parameters: &SpectrumParameters) -> f32 {
+      let k = crate::wave::wavevector_from_coords(coords, size, physical_size);
+      let (min_wavenumber, max_wavenumber) = parameters.wavenumber_band;
+      if k.length() < min_wavenumber || k.length() >= max_wavenumber {
+         return 0.0;
+      }
+      let dk = 2.0 * consts::PI / physical_size;
+      parameters.amplitude * Self::small_wave_cutoff(k, parameters.length_cutoff_meters)
+         * crate::wave::directional_spectrum(
+            parameters.spectrum, parameters.spreading, k, parameters.wind, parameters.water_depth)
+         * dk * dk
    }
+
+   // Expected variance of height, i.e. integral of the spectrum over the lattice
+   pub fn height_variance(size: usize, physical_size: f32, parameters: &SpectrumParameters) -> f32 {
+      let mut variance = 0.0;
+      for row in 0..size {
+         for col in 0..size {
+            if !Self::is_nyquist_frequency((row, col)) {
+               variance += Self::wave_energy((row, col), size, physical_size, parameters);
+            }
+         }
+      }
+      variance
+   }
+
+   // Wavevectors with -N/2 coordinate have no mirrored counterpart on the lattice
+   // (+N/2 is out of [-N/2..N/2) range), so they can't make a real-valued wave
+   fn is_nyquist_frequency((row, col): (usize, usize)) -> bool {
+      row == 0 || col == 0
+   }
+
    // Create empty textures, where we'll be writing the results
    // Since FFT is computed in O(logN) stages, we need to read result of 
    // previous stage from one texture, then write result in the other
    // texture, after that the textures are swapped
+   // Intermediate results are complex, so they take 2 channels
    fn make_height_field(display: &glium::Display, size: usize) -> TextureResult<(Texture2d, Texture2d)> {
       let f0 = glium::Texture2d::empty_with_format(display,
-         glium::texture::UncompressedFloatFormat::F32,
+         glium::texture::UncompressedFloatFormat::F32F32F32F32,
          glium::texture::MipmapsOption::NoMipmap,
       size as u32, size as u32);
       let f1 = glium::Texture2d::empty_with_format(display,
-         glium::texture::UncompressedFloatFormat::F32,
+         glium::texture::UncompressedFloatFormat::F32F32F32F32,
          glium::texture::MipmapsOption::NoMipmap,
       size as u32, size as u32);
       f0.and_then(|f0|
-         f1.and_then(|f1|
-            Ok((f0, f1))))
+         f1.map(|f1| (f0, f1)))
+   }
+
+   // Create empty texture, where final real-valued displacement will be written
+   // (same format is used for maps derived from it)
+   fn make_displacement_map(display: &glium::Display, size: usize) -> TextureResult<Texture2d> {
+      glium::Texture2d::empty_with_format(display,
+         glium::texture::UncompressedFloatFormat::F32F32F32F32,
+         glium::texture::MipmapsOption::NoMipmap,
+         size as u32, size as u32)
    }
 
    // Create empty textures, where spectrum at moment t will be computed (on GPU)
@@ -374,28 +838,13 @@ impl HeightField {
          size as u32, size as u32);
       dx.and_then(|dx|
          dy.and_then(|dy|
-            dz.and_then(|dz|
-            Ok((dx, dy, dz)))))
+            dz.map(|dz|
+            (dx, dy, dz))))
    }
 
-   // The most typical spectrum of oceanic waves, has many researched extensions
-   // to improve convergence or impose requirements (like shallow water)
-   fn phillips_spectrum(amplitude: f32, wave_vector: glam::Vec2, wave_cutoff: f32, wind: &Wind) -> f32 {
-      let k_len = wave_vector.length();
-      let k_sqr = k_len * k_len;
-      let k_4 = k_sqr * k_sqr;
-
-      let largest_wave_len = wind.largest_wavelength();
-      let numerator = f32::exp(-1.0 / (k_sqr * largest_wave_len * largest_wave_len));
-
-      let wind_dot_4 = wave_vector.dot(wind.direction()) / k_len;
-      let wind_dot_4 = wind_dot_4 * wind_dot_4;
-      let wind_dot_4 = wind_dot_4 * wind_dot_4;
-
-      let small_wave_cutoff = f32::exp(
-         -k_sqr * wave_cutoff * wave_cutoff);
-
-      amplitude * numerator * wind_dot_4 * small_wave_cutoff / k_4
+   // Suppress waves much shorter than the cutoff length, to improve convergence
+   fn small_wave_cutoff(wave_vector: glam::Vec2, wave_cutoff: f32) -> f32 {
+      f32::exp(-wave_vector.length_squared() * wave_cutoff * wave_cutoff)
    }
 }
 
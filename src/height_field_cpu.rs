@@ -0,0 +1,389 @@
+// HeightFieldCpu - the same FFT-based algorithm for water height field
+// generation at moment t, as in HeightField, but computed entirely on CPU
+// Doesn't need OpenGL, so it can run headless, and serves as a reference
+// for results computed on GPU
+
+use crate::complex::{complex_conjugate, complex_exp, complex_mul};
+use crate::consts;
+use crate::height_field::{HeightField, SpectrumParameters, TextureData};
+
+// Displacement of one point of water surface
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Displacement {
+   pub dx: f32,
+   pub height: f32,
+   pub dz: f32,
+}
+
+impl std::ops::Add for Displacement {
+   type Output = Displacement;
+   fn add(self, other: Displacement) -> Displacement {
+      Displacement {
+         dx: self.dx + other.dx,
+         height: self.height + other.height,
+         dz: self.dz + other.dz,
+      }
+   }
+}
+
+impl Displacement {
+   // Choppy waves move the surface point at xz to (xz - choppiness*(dx, dz)),
+   // so to find displacement at the given point, we look for xz, which
+   // is moved exactly there (fixed point iteration, converges while
+   // the surface doesn't fold over itself)
+   pub fn at_displaced_point(point_xz: glam::Vec2, choppiness: f32,
+      displacement_at: impl Fn(glam::Vec2) -> Displacement) -> Displacement {
+      let mut xz = point_xz;
+      let mut displacement = displacement_at(xz);
+      for _ in 0..consts::DISPLACEMENT_INVERSION_ITERATIONS {
+         xz = point_xz + choppiness * glam::vec2(displacement.dx, displacement.dz);
+         displacement = displacement_at(xz);
+      }
+      displacement
+   }
+}
+
+// Displacement of water surface in all 3 dimensions
+// Values are stored row by row, i.e. value at (row, col) has index row*size + col
+pub struct DisplacementGrid {
+   pub size: usize,
+   pub dx: Vec<f32>, // displacement x
+   pub dy: Vec<f32>, // height y
+   pub dz: Vec<f32>, // displacement z
+}
+
+impl DisplacementGrid {
+   pub fn zeros(size: usize) -> Self {
+      Self {
+         size,
+         dx: vec![0.0; size*size],
+         dy: vec![0.0; size*size],
+         dz: vec![0.0; size*size],
+      }
+   }
+
+   // From displacement map texture layout (R - x, G - y, B - z)
+   pub fn from_texture_data(size: usize, texture_data: &TextureData) -> Self {
+      let mut grid = Self::zeros(size);
+      for (row, texels) in texture_data.iter().enumerate() {
+         for (col, texel) in texels.iter().enumerate() {
+            grid.dx[row*size + col] = texel.0;
+            grid.dy[row*size + col] = texel.1;
+            grid.dz[row*size + col] = texel.2;
+         }
+      }
+      grid
+   }
+
+   // Texels of RGBA float texture, as they're stored in a pixel buffer
+   pub fn from_rgba(size: usize, texels: &[f32]) -> Self {
+      let mut grid = Self::zeros(size);
+      for (idx, texel) in texels.chunks_exact(4).enumerate().take(size*size) {
+         grid.dx[idx] = texel[0];
+         grid.dy[idx] = texel[1];
+         grid.dz[idx] = texel[2];
+      }
+      grid
+   }
+
+   // Displacement at any point, the grid covers a square of physical_size meters
+   // with texel (0, 0) at the origin, and repeats beyond it
+   // Values between texels are bilinearly interpolated
+   pub fn sample_bilinear(&self, xz: glam::Vec2, physical_size: f32) -> Displacement {
+      let size = self.size;
+      let texel = xz / physical_size * size as f32;
+      let (col, row) = (texel.x.floor(), texel.y.floor());
+      let (fx, fz) = (texel.x - col, texel.y - row);
+      let wrap = |value: f32| value.rem_euclid(size as f32) as usize % size;
+      let (col0, row0) = (wrap(col), wrap(row));
+      let (col1, row1) = ((col0 + 1) % size, (row0 + 1) % size);
+      let lerp = |values: &Vec<f32>| {
+         let top = values[row0*size + col0] * (1.0 - fx) + values[row0*size + col1] * fx;
+         let bottom = values[row1*size + col0] * (1.0 - fx) + values[row1*size + col1] * fx;
+         top * (1.0 - fz) + bottom * fz
+      };
+      Displacement { dx: lerp(&self.dx), height: lerp(&self.dy), dz: lerp(&self.dz) }
+   }
+
+   // Displacement of the surface, that is at the given point after choppy displacement
+   // (i.e. height of water right at this point)
+   pub fn sample(&self, xz: glam::Vec2, physical_size: f32, choppiness: f32) -> Displacement {
+      Displacement::at_displaced_point(xz, choppiness,
+         |xz| self.sample_bilinear(xz, physical_size))
+   }
+
+   // Counterpart of "surface.comp" GPU program: unit normals of the displaced surface,
+   // found with central finite differences, wrapping around the borders
+   pub fn normals(&self, physical_size: f32, choppiness: f32) -> Vec<glam::Vec3> {
+      let size = self.size;
+      let texel_meters = physical_size / size as f32;
+      let at = |row: usize, col: usize| {
+         let idx = (row % size)*size + col % size;
+         glam::vec3(self.dx[idx], self.dy[idx], self.dz[idx])
+      };
+      (0..size*size).map(|idx| {
+         let (row, col) = (idx / size, idx % size);
+         let d_dx = (at(row, col + 1) - at(row, col + size - 1)) / (2.0 * texel_meters);
+         let d_dz = (at(row + 1, col) - at(row + size - 1, col)) / (2.0 * texel_meters);
+         let tangent_x = glam::vec3(1.0 - choppiness * d_dx.x, d_dx.y, -choppiness * d_dx.z);
+         let tangent_z = glam::vec3(-choppiness * d_dz.x, d_dz.y, 1.0 - choppiness * d_dz.z);
+         tangent_z.cross(tangent_x).normalize()
+      }).collect()
+   }
+
+   // The largest difference between the same values of two grids
+   pub fn max_abs_difference(&self, other: &DisplacementGrid) -> f32 {
+      assert_eq!(self.size, other.size);
+      [(&self.dx, &other.dx), (&self.dy, &other.dy), (&self.dz, &other.dz)].iter()
+         .flat_map(|(a, b)| a.iter().zip(b.iter()))
+         .map(|(a, b)| f32::abs(a - b))
+         .fold(0.0, f32::max)
+   }
+}
+
+pub struct HeightFieldCpu {
+   // size of computing domain, has to be a power of 2
+   size: usize,
+   physical_size: f32, // meters
+
+   // period of global ocean motion
+   period_sec: f32,
+   // meters, infinite for deep water
+   water_depth: f32,
+
+   // same layout as textures of HeightField
+   twiddle_indices: TextureData,
+   base_spectrum: TextureData,
+   base_spectrum_minus_k: TextureData,
+}
+
+impl HeightFieldCpu {
+   // Parameters can be taken from HeightField::spectrum_parameters(),
+   // then both HeightField and HeightFieldCpu simulate exactly the same waves
+   pub fn new(lattice_size: usize, physical_size: f32, period_sec: f32, parameters: &SpectrumParameters) -> Self {
+      let (base_spectrum, base_spectrum_minus_k) = HeightField::make_base_spectrum_cpu(
+         lattice_size, physical_size, parameters);
+      Self {
+         size: lattice_size,
+         physical_size,
+         period_sec,
+         water_depth: parameters.water_depth,
+         twiddle_indices: HeightField::make_twiddle_indices_cpu(lattice_size),
+         base_spectrum,
+         base_spectrum_minus_k,
+      }
+   }
+
+   // Same as HeightField::compute_height_field_gpu, but the result is returned
+   pub fn compute_height_field_cpu(&self, time: f32) -> DisplacementGrid {
+      let (spectrum_dx, spectrum_dy, spectrum_dz) = self.compute_spectrum_realizations(time);
+      DisplacementGrid {
+         size: self.size,
+         dx: self.compute_inverse_fft(spectrum_dx),
+         dy: self.compute_inverse_fft(spectrum_dy),
+         dz: self.compute_inverse_fft(spectrum_dz),
+      }
+   }
+
+   // To make simulation periodic, we need to make all subwaves frequencies
+   // to be a multiple of some base frequency
+   pub fn base_frequency(&self) -> f32 {
+      2.0 * consts::PI / self.period_sec
+   }
+
+   // Counterpart of "fft.comp" GPU program
+   fn compute_spectrum_realizations(&self, time: f32) -> (Vec<glam::Vec2>, Vec<glam::Vec2>, Vec<glam::Vec2>) {
+      let mut hkt_dx = vec![glam::Vec2::ZERO; self.size*self.size];
+      let mut hkt_dy = vec![glam::Vec2::ZERO; self.size*self.size];
+      let mut hkt_dz = vec![glam::Vec2::ZERO; self.size*self.size];
+      for row in 0..self.size {
+         for col in 0..self.size {
+            let wavevector = crate::wave::wavevector_from_coords(
+               (row, col), self.size, self.physical_size);
+            let wavevector_mag = f32::max(wavevector.length(), 0.0001);
+
+            let w = crate::wave::discrete_dispersion_frequency(
+               crate::wave::dispersion_frequency_at_depth(wavevector_mag, self.water_depth),
+               self.base_frequency());
+            let e_iwt = complex_exp(glam::vec2(0.0, w*time));
+            let e_iwt_inv = complex_conjugate(e_iwt);
+
+            let (h0k_x, h0k_y, _, _) = self.base_spectrum[row][col];
+            let (h0_minus_k_x, h0_minus_k_y, _, _) = self.base_spectrum_minus_k[row][col];
+            let h0k = glam::vec2(h0k_x, h0k_y);
+            let h0_minus_k = complex_conjugate(glam::vec2(h0_minus_k_x, h0_minus_k_y));
+
+            let dy = complex_mul(h0k, e_iwt) + complex_mul(h0_minus_k, e_iwt_inv);
+            let idx = row*self.size + col;
+            hkt_dy[idx] = dy;
+            hkt_dx[idx] = complex_mul(glam::vec2(0.0, -wavevector.x/wavevector_mag), dy);
+            hkt_dz[idx] = complex_mul(glam::vec2(0.0, -wavevector.y/wavevector_mag), dy);
+         }
+      }
+      (hkt_dx, hkt_dy, hkt_dz)
+   }
+
+   // Counterpart of "butterfly.comp" and "displacement.comp" GPU programs
+   // Imaginary part is dropped, as spectrum realizations are conjugate symmetric
+   fn compute_inverse_fft(&self, spectrum: Vec<glam::Vec2>) -> Vec<f32> {
+      self.compute_inverse_fft_complex(spectrum).iter()
+         .map(|value| value.x)
+         .collect()
+   }
+
+   // logN horizontal stages, then logN vertical stages, then sign correction
+   fn compute_inverse_fft_complex(&self, spectrum: Vec<glam::Vec2>) -> Vec<glam::Vec2> {
+      let size = self.size;
+      let n_stages = usize::trailing_zeros(size) as usize; // == log2(size)
+      let mut input = spectrum;
+      let mut output = vec![glam::Vec2::ZERO; size*size];
+      for direction in 0..2 {
+         for stage in 0..n_stages {
+            for row in 0..size {
+               for col in 0..size {
+                  let (twiddle_x, twiddle_y, index, other_index) = if direction == 0 {
+                     self.twiddle_indices[col][stage]
+                  } else {
+                     self.twiddle_indices[row][stage]
+                  };
+                  let (p, q) = if direction == 0 {
+                     (input[row*size + index as usize], input[row*size + other_index as usize])
+                  } else {
+                     (input[index as usize * size + col], input[other_index as usize * size + col])
+                  };
+                  output[row*size + col] = p + complex_mul(glam::vec2(twiddle_x, twiddle_y), q);
+               }
+            }
+            std::mem::swap(&mut input, &mut output);
+         }
+      }
+
+      (0..size*size).map(|idx| {
+         let (row, col) = (idx / size, idx % size);
+         let sign_correction = if (row + col) % 2 == 0 { 1.0 } else { -1.0 };
+         sign_correction * input[idx]
+      }).collect()
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::wave::{Wind, JonswapSpectrum, Cos2sSpreading};
+
+   const SIZE: usize = 64;
+   const PHYSICAL_SIZE: f32 = 500.0;
+
+   fn make_models() -> (Wind, JonswapSpectrum, Cos2sSpreading) {
+      (Wind::new(consts::WIND_VELOCITY,
+         glam::vec2(consts::WIND_DIRECTION_X, consts::WIND_DIRECTION_Y)),
+       JonswapSpectrum {
+         peak_enhancement: consts::JONSWAP_PEAK_ENHANCEMENT,
+         fetch: consts::JONSWAP_FETCH_METERS,
+       },
+       Cos2sSpreading { exponent: consts::SPREADING_EXPONENT })
+   }
+
+   fn make_parameters<'a>((wind, spectrum, spreading): &'a (Wind, JonswapSpectrum, Cos2sSpreading), seed: u64) -> SpectrumParameters<'a> {
+      SpectrumParameters {
+         amplitude: consts::SPECTRUM_AMPLITUDE,
+         length_cutoff_meters: consts::WAVELENGTH_CUTOFF_METERS,
+         wind, spectrum, spreading, seed,
+         wavenumber_band: (0.0, f32::INFINITY),
+         water_depth: f32::INFINITY,
+      }
+   }
+
+   // Butterflies with the twiddle table give the same values, as the sum of
+   // all complex exponents (wavevectors are centered at the middle of the lattice)
+   #[test]
+   fn inverse_fft_matches_direct_sum() {
+      let size = 8;
+      let models = make_models();
+      let height_field = HeightFieldCpu::new(size, PHYSICAL_SIZE, 10.0,
+         &make_parameters(&models, consts::RANDOM_SEED));
+      let spectrum = (0..size*size)
+         .map(|idx| glam::vec2(f32::sin(idx as f32 * 1.7), f32::cos(idx as f32 * 0.3)))
+         .collect::<Vec<_>>();
+      let values = height_field.compute_inverse_fft_complex(spectrum.clone());
+      for (idx, value) in values.iter().enumerate() {
+         let (z, x) = (idx / size, idx % size);
+         let expected = spectrum.iter().enumerate().fold(glam::Vec2::ZERO, |sum, (k_idx, amplitude)| {
+            let (row, col) = (k_idx / size, k_idx % size);
+            let phase = 2.0 * consts::PI / size as f32 * ((col as f32 - (size / 2) as f32) * x as f32
+               + (row as f32 - (size / 2) as f32) * z as f32);
+            sum + complex_mul(*amplitude, complex_exp(glam::vec2(0.0, phase)))
+         });
+         assert!(value.abs_diff_eq(expected, 1e-3), "value {} vs {} at ({}, {})", value, expected, x, z);
+      }
+   }
+
+   #[test]
+   fn height_field_is_real() {
+      let models = make_models();
+      let height_field = HeightFieldCpu::new(SIZE, PHYSICAL_SIZE, 10.0,
+         &make_parameters(&models, consts::RANDOM_SEED));
+      for time in [0.0, 1.3, 7.9] {
+         let (spectrum_dx, spectrum_dy, spectrum_dz) = height_field.compute_spectrum_realizations(time);
+         for spectrum in [spectrum_dx, spectrum_dy, spectrum_dz] {
+            let values = height_field.compute_inverse_fft_complex(spectrum);
+            let max_real = values.iter().fold(0.0, |max, value| f32::max(max, value.x.abs()));
+            let max_imag = values.iter().fold(0.0, |max, value| f32::max(max, value.y.abs()));
+            assert!(max_real > 0.0);
+            assert!(max_imag < 1e-4 * max_real, "imaginary part {} vs real part {}", max_imag, max_real);
+         }
+      }
+   }
+
+   #[test]
+   fn normals_of_sine_wave() {
+      // h = a*sin(kx), so the normal is proportional to (-a*k*cos(kx), 1, 0)
+      let (amplitude, wavenumber) = (2.0, 2.0 * consts::PI / PHYSICAL_SIZE);
+      let mut grid = DisplacementGrid::zeros(SIZE);
+      for (idx, height) in grid.dy.iter_mut().enumerate() {
+         let x = (idx % SIZE) as f32 * PHYSICAL_SIZE / SIZE as f32;
+         *height = amplitude * f32::sin(wavenumber * x);
+      }
+      for (idx, normal) in grid.normals(PHYSICAL_SIZE, 1.0).iter().enumerate() {
+         let x = (idx % SIZE) as f32 * PHYSICAL_SIZE / SIZE as f32;
+         let expected = glam::vec3(-amplitude * wavenumber * f32::cos(wavenumber * x), 1.0, 0.0).normalize();
+         assert!(normal.abs_diff_eq(expected, 1e-4), "normal {} vs expected {}", normal, expected);
+      }
+   }
+
+   #[test]
+   fn sample_at_displaced_texels() {
+      let models = make_models();
+      let grid = HeightFieldCpu::new(SIZE, PHYSICAL_SIZE, 10.0,
+         &make_parameters(&models, consts::RANDOM_SEED)).compute_height_field_cpu(1.0);
+      let choppiness = 0.5;
+      let texel_meters = PHYSICAL_SIZE / SIZE as f32;
+      for idx in (0..SIZE*SIZE).step_by(37) {
+         let xz = glam::vec2((idx % SIZE) as f32, (idx / SIZE) as f32) * texel_meters;
+         let displaced_xz = xz - choppiness * glam::vec2(grid.dx[idx], grid.dz[idx]);
+         let sampled = grid.sample(displaced_xz, PHYSICAL_SIZE, choppiness);
+         assert!((sampled.height - grid.dy[idx]).abs() < 1e-2,
+            "height {} vs {} at {}", sampled.height, grid.dy[idx], xz);
+      }
+   }
+
+   #[test]
+   fn height_variance_matches_spectrum_integral() {
+      let models = make_models();
+      let expected_variance = HeightField::height_variance(
+         SIZE, PHYSICAL_SIZE, &make_parameters(&models, 0));
+
+      // variance of a single realization is random, so average over many seeds
+      let n_seeds = 32;
+      let mean_variance = (0..n_seeds).map(|seed| {
+         let heights = HeightFieldCpu::new(SIZE, PHYSICAL_SIZE, 10.0, &make_parameters(&models, seed))
+            .compute_height_field_cpu(2.5).dy;
+         let mean = heights.iter().sum::<f32>() / heights.len() as f32;
+         heights.iter().map(|h| (h - mean) * (h - mean)).sum::<f32>() / heights.len() as f32
+      }).sum::<f32>() / n_seeds as f32;
+
+      let relative_error = (mean_variance - expected_variance).abs() / expected_variance;
+      assert!(relative_error < 0.1,
+         "variance {} vs spectrum integral {}", mean_variance, expected_variance);
+   }
+}
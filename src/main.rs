@@ -1,14 +1,13 @@
 // Entry point:
 // Setting up a window, logging library
 // Starting an infinite drawing loop
+// (or running simulation without a window, when launched with --headless)
 
 use std::{borrow::Cow, time::SystemTime};
 
 use imgui::{Key, MouseButton, CollapsingHeader};
 use render::{Renderer, DrawMode};
 
-use crate::height_field::HeightField;
-
 // Link other source code files
 mod shader;
 mod render;
@@ -17,6 +16,18 @@ mod camera;
 mod window;
 mod wave;
 mod height_field;
+mod height_field_cpu;
+mod ocean_cascades;
+mod readback;
+mod buoyancy;
+mod buoys;
+mod statistics;
+mod weather;
+mod config;
+mod beaufort;
+mod headless;
+mod export;
+mod mesh_export;
 mod consts;
 mod complex;
 
@@ -26,49 +37,172 @@ fn main() {
    TermLogger::init(LogLevelFilter::Info)
       .expect("Failed to initialize logger");
 
-   let mut window = window::Window::new(
+   let args = std::env::args().skip(1).collect::<Vec<_>>();
+   if args.iter().any(|arg| arg == "--headless") {
+      headless::main(&args);
+      return;
+   }
+
+   // parameters from --config <path>, the defaults otherwise
+   let initial_config = match args.iter().position(|arg| arg == "--config") {
+      Some(idx) => {
+         let path = match args.get(idx + 1) {
+            Some(path) => std::path::PathBuf::from(path),
+            None => {
+               log::error!("Missing value of argument --config");
+               std::process::exit(1);
+            }
+         };
+         match config::SimulationConfig::load(&path) {
+            Ok(config) => {
+               log::info!("Loaded config from {}", path.display());
+               config
+            }
+            Err(error) => {
+               log::error!("Failed to load config {}: {}", path.display(), error);
+               std::process::exit(1);
+            }
+         }
+      }
+      None => config::SimulationConfig::default(),
+   };
+
+   let window = window::Window::new(
          consts::WINDOW_TITLE, (1600, 900), 13.0, None);
    assert!(glium::program::ComputeShader::is_supported(&window.display));
          
    // setting up default simulation parameters
-   let mut fft_domain_size_idx = 4;
    let fft_domain_size_variants = vec![16, 32, 64, 128, 256, 512, 1024];
+   let mut fft_domain_size_idx = fft_domain_size_variants.iter()
+      .position(|&size| size as usize == initial_config.lattice_size).unwrap();
 
    let mut water_size = fft_domain_size_variants[fft_domain_size_idx];
-   let mut water_facet_size = 4.00;
-   let center_x = water_size as f32 * 0.5;
-   let water_center = glam::vec3a(center_x, center_x, 0.0);
+   let mut water_facet_size = initial_config.facet_size;
    let mut water = render::WaterRenderer::new(
       &window.display,
       (water_size, water_size),
       water_facet_size);
 
-   let mut water_period_sec = 10.0;
-   let mut height_field = height_field::HeightField::new(
-      &window.display, water_size as usize, 
-      water_facet_size * water_size as f32, water_period_sec);
+   let mut water_period_sec = initial_config.period_sec;
+   let mut water_choppiness = initial_config.choppiness;
+   water.set_choppiness(water_choppiness);
+   let mut lighting = render::WaterLighting::default();
+   let mut sun_azimuth = consts::SUN_AZIMUTH_DEGREES;
+   let mut sun_elevation = consts::SUN_ELEVATION_DEGREES;
+   let mut spectrum_idx = 0;
+   let spectrum_variants = vec!["Phillips", "Pierson-Moskowitz", "JONSWAP", "TMA"];
+   let mut jonswap_peak_enhancement = consts::JONSWAP_PEAK_ENHANCEMENT;
+   let mut jonswap_fetch_km = consts::JONSWAP_FETCH_METERS / 1000.0;
+   // infinite for deep water
+   let mut water_depth = initial_config.water_depth.unwrap_or(f32::INFINITY);
+   let mut finite_water_depth = initial_config.water_depth.is_some();
+   let mut finite_water_depth_meters = initial_config.water_depth.unwrap_or(consts::TMA_WATER_DEPTH_METERS);
+   let mut deep_water_spectrum_idx = spectrum_idx;
+   if finite_water_depth {
+      switch_spectrum_for_depth(finite_water_depth, &mut spectrum_idx, &mut deep_water_spectrum_idx);
+      lighting.water_depth = water_depth;
+      water.set_lighting(lighting);
+   }
+   let mut spreading_idx = 0;
+   let spreading_variants = vec!["cos-2s", "Mitsuyasu", "Hasselmann", "Donelan-Banner"];
+   let mut spreading_exponent = consts::SPREADING_EXPONENT;
+   let mut spreading_swell = 0.0;
+   let mut seed = initial_config.seed as i32;
+   let mut wind_speed = initial_config.wind_speed;
+   let mut wind_direction_deg = initial_config.wind_direction_deg;
+   let mut spectrum_amplitude = initial_config.spectrum_amplitude;
+   let mut length_cutoff_meters = initial_config.length_cutoff_meters;
+   let mut spectrum_transition_sec = consts::SPECTRUM_TRANSITION_SEC;
+   let mut wind_timeline = weather::WindTimeline::default();
+   let mut new_keyframe = weather::WindKeyframe { time: 0.0, speed: wind_speed, direction_deg: wind_direction_deg };
+   // simulation time, when playing of the timeline started
+   let mut timeline_start_time = None::<f32>;
+   let mut timeline_loop = false;
+   let mut applied_keyframe = None::<weather::WindKeyframe>;
+   let mut cascade_count: u32 = 3;
+   let mut cascade_sizes = default_cascade_sizes(water_facet_size * water_size as f32);
+   let mut ocean = ocean_cascades::OceanCascades::new(
+      &window.display, water_size as usize, &cascade_sizes[..cascade_count as usize], water_period_sec,
+      &|| make_wave_spectrum(spectrum_idx, jonswap_peak_enhancement, jonswap_fetch_km, water_depth),
+      &|| make_directional_spreading(spreading_idx, spreading_exponent, spreading_swell));
+   // the initial sea state appears at once, without transition
+   ocean.set_transition_duration(0.0);
+   ocean.set_choppiness(water_choppiness);
+   ocean.set_wind(&window.display, initial_config.wind());
+   ocean.set_spectrum_amplitude(&window.display, spectrum_amplitude);
+   ocean.set_length_cutoff(&window.display, length_cutoff_meters);
+   ocean.set_seed(&window.display, seed as u64);
+   ocean.set_water_depth(&window.display, water_depth);
+   ocean.set_transition_duration(spectrum_transition_sec);
+   let mut preview_cascade_idx: u32 = 0;
+
+   let presets = config::SimulationConfig::presets();
+   let mut preset_idx = 0;
+   let mut config_path = String::from("config/simulation.toml");
+   // applied at the start of the next frame
+   let mut pending_config = None::<config::SimulationConfig>;
+
+   let mut export_cascade_idx: u32 = 0;
+   let mut export_directory = String::from("export");
+   let mut heightmap_format_idx = 0;
+   let heightmap_format_variants = vec!["PNG 16-bit", "TIFF 32-bit float"];
+   let mut heightmap_export = export::HeightmapExport {
+      format: export::HeightmapFormat::Png16,
+      frame_rate: 30.0,
+      with_horizontal: false,
+   };
+   let mut flipbook_frame_count = 64;
+   let mut mesh_time_sec = 0.0;
+   let mut mesh_format_idx = 0;
+   let mesh_format_variants = vec!["OBJ", "PLY (binary)", "glTF (binary)"];
+   let mut animation_format_idx = 0;
+   let animation_format_variants = vec!["PC2 point cache", "glTF morph targets"];
+   let mut animation_frame_rate = 24.0;
+
+   let mut floating_bodies = buoyancy::BuoyancySimulation::default();
+   let mut body_renderers = Vec::<render::BodyRenderer>::new();
+   let mut body_shape_idx = 0;
+   let body_shape_variants = vec!["Box", "Capsule", "OBJ hull"];
+   let mut body_box_size = [6.0, 1.5, 2.5];
+   let mut body_capsule_radius = 1.0;
+   let mut body_capsule_length = 4.0;
+   let mut body_obj_path = String::from("hull.obj");
+   let mut body_density = 500.0;
+   let mut body_spawn_point = [0.0_f32; 2];
+   let mut buoys = Vec::<buoys::Buoy>::new();
+   let mut buoy_position = [0.0_f32; 2];
+   let mut buoys_csv_path = String::from("export/buoys.csv");
+   let mut buoy_plot_length: u32 = 300;
+   let mut sea_state = None::<statistics::SeaStateComparison>;
+   let mut sea_state_continuous = false;
+   let body_colors = [[0.8, 0.3, 0.2], [0.9, 0.8, 0.3], [0.3, 0.6, 0.3], [0.7, 0.7, 0.75]];
 
    let mut camera = camera::Camera::default();
    let default_camera_translation = glam::vec3a(0.0, -20.0, -1.0);
    let default_camera_direction = -glam::vec3a(1.0, 1.0, 1.0).normalize();
    camera
-      .translate_to(default_camera_translation)
+      .translate_to(glam::Vec3A::from(initial_config.camera.position))
       .look_forward(default_camera_direction)
       ;
 
    let mut camera_steer_sensitivity = consts::CAMERA_DEFAULT_STEER_SENSITIVITY;
-   let mut camera_orient_sensitivity = consts::CAMERA_DEFAULT_ORIENT_SENSITIVITY;
-   let mut draw_mode = DrawMode::Wireframe;
-   let (mut yaw, mut pitch) = (-90.0, 0.0);
+   let camera_orient_sensitivity = consts::CAMERA_DEFAULT_ORIENT_SENSITIVITY;
+   let mut draw_mode = initial_config.draw_mode;
+   let (mut yaw, mut pitch) = (initial_config.camera.yaw_deg, initial_config.camera.pitch_deg);
 
    let mut show_precomputed_twiddle_texture: bool = true;
    let mut show_precomputed_spectrum_textures: bool = true;
    let mut show_spectrum_realization_textures: bool = false;
    let mut show_height_field_texture: bool = false;
+   let mut show_surface_textures: bool = false;
+   let mut async_readback = false;
+   let mut readback_buffer_count = consts::READBACK_BUFFER_COUNT as u32;
 
    let mut debug_textures_size = 200_f32;
+   let mut sample_point = [0.0_f32; 2];
 
    let sys_time = SystemTime::now();
+   let mut height_field_time = 0.0;
 
    window.run_loop(move |run, ui, display, frame| {
       let mut camera_steer = glam::Vec3A::ZERO;
@@ -81,13 +215,63 @@ fn main() {
          consts::CAMERA_FAR_PLANE,
       );
 
+      // the lattice and the mesh are recreated only if their sizes change,
+      // the sea state fades in like after changing the wind
+      if let Some(config) = pending_config.take() {
+         fft_domain_size_idx = fft_domain_size_variants.iter()
+            .position(|&size| size as usize == config.lattice_size).unwrap();
+         if water_size != config.lattice_size as u32 || water_facet_size != config.facet_size {
+            water_size = config.lattice_size as u32;
+            water_facet_size = config.facet_size;
+            water.recreate_mesh_grid(display, (water_size, water_size), water_facet_size);
+            cascade_sizes = default_cascade_sizes(water_facet_size * water_size as f32);
+            ocean.set_physical_sizes(display, water_size as usize, &cascade_sizes[..cascade_count as usize],
+               &|| make_wave_spectrum(spectrum_idx, jonswap_peak_enhancement, jonswap_fetch_km, water_depth),
+               &|| make_directional_spreading(spreading_idx, spreading_exponent, spreading_swell));
+         }
+         water_period_sec = config.period_sec;
+         ocean.set_period(water_period_sec);
+         water_choppiness = config.choppiness;
+         water.set_choppiness(water_choppiness);
+         ocean.set_choppiness(water_choppiness);
+         wind_speed = config.wind_speed;
+         wind_direction_deg = config.wind_direction_deg;
+         ocean.set_wind(display, config.wind());
+         spectrum_amplitude = config.spectrum_amplitude;
+         ocean.set_spectrum_amplitude(display, spectrum_amplitude);
+         length_cutoff_meters = config.length_cutoff_meters;
+         ocean.set_length_cutoff(display, length_cutoff_meters);
+         if seed != config.seed as i32 {
+            seed = config.seed as i32;
+            ocean.set_seed(display, seed as u64);
+         }
+         if config.water_depth.is_some() != finite_water_depth
+            || config.water_depth.is_some_and(|depth| depth != finite_water_depth_meters) {
+            finite_water_depth = config.water_depth.is_some();
+            finite_water_depth_meters = config.water_depth.unwrap_or(finite_water_depth_meters);
+            water_depth = config.water_depth.unwrap_or(f32::INFINITY);
+            ocean.set_water_depth(display, water_depth);
+            switch_spectrum_for_depth(finite_water_depth, &mut spectrum_idx, &mut deep_water_spectrum_idx);
+            if finite_water_depth {
+               lighting.water_depth = water_depth;
+               water.set_lighting(lighting);
+            }
+            ocean.set_spectrum(display, &|| make_wave_spectrum(
+               spectrum_idx, jonswap_peak_enhancement, jonswap_fetch_km, water_depth));
+         }
+         draw_mode = config.draw_mode;
+         camera.translate_to(glam::Vec3A::from(config.camera.position));
+         yaw = config.camera.yaw_deg;
+         pitch = config.camera.pitch_deg;
+      }
+
       imgui::Window::new("Demo settings")
          .size([350.0, 400.0], imgui::Condition::FirstUseEver)
          .position([20.0, 20.0], imgui::Condition::Appearing)
          .opened(run)
          .size_constraints([300.0, 300.0], [600.0, 600.0])
          .resizable(true)
-         .build(&ui, || {
+         .build(ui, || {
                if ui.is_key_down(Key::LeftArrow) {
                   camera_steer.x = -1.0;
                }
@@ -111,9 +295,9 @@ fn main() {
                if is_right_mouse {
                   camera_steer.y += ui.io().mouse_delta[1] * camera_orient_sensitivity * 5.0;
                }
-               ui.text(format!(
+               ui.text(
                   "Usage:\n- Arrows L/R : Camera side-steer\n- Arrows U/D : Camera forward/back\n- Left Mouse Btn Drag: Camera rotation\n- Right Mouse Btn Drag: Camera up/down",
-               ));
+               );
 
                if CollapsingHeader::new("Debug").build(ui) {
                   ui.text(format!(
@@ -129,10 +313,58 @@ fn main() {
 
                   ui.checkbox("Show precomputed twiddle", &mut show_precomputed_twiddle_texture);
                   ui.checkbox("Show precomputed spectrum", &mut show_precomputed_spectrum_textures);
-                  ui.checkbox("Show spectrum realization", &mut &mut show_spectrum_realization_textures);
-                  ui.checkbox("Show height field", &mut &mut show_height_field_texture);
+                  ui.checkbox("Show spectrum realization", &mut show_spectrum_realization_textures);
+                  ui.checkbox("Show height field", &mut show_height_field_texture);
+                  ui.checkbox("Show normal and Jacobian", &mut show_surface_textures);
                   imgui::Slider::new("Preview width (px)", 100.0, 300.0)
-                     .build(&ui, &mut debug_textures_size);
+                     .build(ui, &mut debug_textures_size);
+                  imgui::Slider::new("Preview cascade", 0, cascade_count - 1)
+                     .build(ui, &mut preview_cascade_idx);
+                  ui.text(format!("Cascade significant wave height: {:.2} m",
+                     ocean.cascades()[preview_cascade_idx as usize].significant_wave_height()));
+
+                  if ui.button("Compare with CPU reference") {
+                     let height_field = &ocean.cascades()[preview_cascade_idx as usize];
+                     // the reference has only the new spectrum
+                     if height_field.spectrum_blend() < 1.0 {
+                        log::warn!("Spectrum transition is in progress, compare after it ends");
+                     } else {
+                        let reference = height_field_cpu::HeightFieldCpu::new(
+                           height_field.size(), height_field.physical_size(), height_field.period(),
+                           &height_field.spectrum_parameters());
+                        let difference = reference.compute_height_field_cpu(height_field_time)
+                           .max_abs_difference(&height_field.read_displacement());
+                        log::info!("Max difference of GPU and CPU displacement: {}", difference);
+                     }
+                  }
+
+                  ui.checkbox("Asynchronous readback", &mut async_readback);
+                  if imgui::Slider::new("Readback buffers", 1, 4)
+                     .build(ui, &mut readback_buffer_count) {
+                     ocean.set_readback_buffer_count(readback_buffer_count as usize);
+                  }
+                  ui.input_float2("Sample point (m)", &mut sample_point).build();
+                  if async_readback {
+                     let height_field = &ocean.cascades()[preview_cascade_idx as usize];
+                     match (height_field.readback_latency_frames(), height_field.latest_displacement()) {
+                        (Some(latency), Some(latest)) => {
+                           let grid_xz = water.world_to_grid(glam::Vec2::from(sample_point));
+                           let displacement = latest.grid.sample(grid_xz,
+                              height_field.physical_size(), water_choppiness);
+                           ui.text(format!("Readback latency: {} frames", latency));
+                           ui.text(format!("Height at sample point: {:.3} m (at {:.2} sec)",
+                              displacement.height, latest.time));
+                        }
+                        _ => ui.text("Readback latency: waiting for the first frame"),
+                     }
+                  }
+                  if ui.button("Sample water") {
+                     let height_field = &ocean.cascades()[preview_cascade_idx as usize];
+                     let grid_xz = water.world_to_grid(glam::Vec2::from(sample_point));
+                     let displacement = height_field.sample(grid_xz, height_field_time);
+                     log::info!("Cascade displacement at ({}, {}): height {:.3} m, horizontal ({:.3}, {:.3}) m",
+                        sample_point[0], sample_point[1], displacement.height, displacement.dx, displacement.dz);
+                  }
                }
 
                if CollapsingHeader::new("Camera").default_open(true).build(ui) {
@@ -143,12 +375,55 @@ fn main() {
                   }
                   // ui.item_size([])
                   imgui::Slider::new("Sensitivity", 1.0, 500.0)
-                     .build(&ui, &mut camera_steer_sensitivity);
+                     .build(ui, &mut camera_steer_sensitivity);
 
                   ui.radio_button("Render textured", &mut draw_mode, DrawMode::Mesh);
                   ui.radio_button("Render wireframe", &mut draw_mode, DrawMode::Wireframe);
                }
 
+               if CollapsingHeader::new("Configuration").build(ui) {
+                  ui.combo("Preset", &mut preset_idx, &presets,
+                     |(preset_name, _)| Cow::Borrowed(preset_name));
+                  ui.same_line();
+                  if ui.button("Apply") {
+                     pending_config = Some(presets[preset_idx].1.clone());
+                  }
+                  ui.input_text("Config file (.toml, .json)", &mut config_path).build();
+                  if ui.button("Load") {
+                     let path = std::path::Path::new(&config_path);
+                     match config::SimulationConfig::load(path) {
+                        Ok(config) => pending_config = Some(config),
+                        Err(error) => log::error!("Failed to load config {}: {}", path.display(), error),
+                     }
+                  }
+                  ui.same_line();
+                  if ui.button("Save") {
+                     let config = config::SimulationConfig {
+                        lattice_size: water_size as usize,
+                        facet_size: water_facet_size,
+                        period_sec: water_period_sec,
+                        choppiness: water_choppiness,
+                        wind_speed,
+                        wind_direction_deg,
+                        spectrum_amplitude,
+                        length_cutoff_meters,
+                        seed: seed as u64,
+                        water_depth: finite_water_depth.then_some(finite_water_depth_meters),
+                        draw_mode,
+                        camera: config::CameraConfig {
+                           position: camera.position().to_array(),
+                           yaw_deg: yaw,
+                           pitch_deg: pitch,
+                        },
+                     };
+                     let path = std::path::Path::new(&config_path);
+                     match config.save(path) {
+                        Ok(()) => log::info!("Saved config to {}", path.display()),
+                        Err(error) => log::error!("Failed to save config {}: {}", path.display(), error),
+                     }
+                  }
+               }
+
                if CollapsingHeader::new("Water")
                   .default_open(true).build(ui) {
                   let fft_domain_size_changed = ui.combo("Lattice size",
@@ -161,13 +436,466 @@ fn main() {
 
                   if fft_domain_size_changed || facet_size_changed {
                      water_size = fft_domain_size_variants[fft_domain_size_idx];
-                     //water_facet_size = water_facet_size.clamp(0.01, 1.0);
+                     water_facet_size = water_facet_size.max(0.01);
                      water.recreate_mesh_grid(display, (water_size, water_size), water_facet_size);
-                     height_field.regenerate_textures(display, water_size as usize,
-                        water_facet_size * water_size as f32);
+                     cascade_sizes = default_cascade_sizes(water_facet_size * water_size as f32);
                   };
                   if water_period_changed {
-                     height_field.set_period(water_period_sec);
+                     ocean.set_period(water_period_sec);
+                  }
+
+                  let mut cascades_changed = fft_domain_size_changed || facet_size_changed;
+                  cascades_changed |= imgui::Slider::new("Cascades", 2, consts::MAX_CASCADES as u32)
+                     .build(ui, &mut cascade_count);
+                  for (idx, cascade_size) in cascade_sizes[..cascade_count as usize].iter_mut().enumerate() {
+                     if ui.input_float(format!("Cascade {} size (m)", idx), cascade_size).build() {
+                        *cascade_size = cascade_size.max(consts::MIN_CASCADE_SIZE_METERS);
+                        cascades_changed = true;
+                     }
+                  }
+                  if cascades_changed {
+                     ocean.set_physical_sizes(display, water_size as usize, &cascade_sizes[..cascade_count as usize],
+                        &|| make_wave_spectrum(spectrum_idx, jonswap_peak_enhancement, jonswap_fetch_km, water_depth),
+                        &|| make_directional_spreading(spreading_idx, spreading_exponent, spreading_swell));
+                     preview_cascade_idx = preview_cascade_idx.min(cascade_count - 1);
+                  }
+
+                  // the force follows the wind speed, choosing a force sets
+                  // all parameters of its sea state
+                  let mut beaufort_idx = beaufort::force_of_wind(wind_speed).number as usize;
+                  if ui.combo("Beaufort force", &mut beaufort_idx, &beaufort::BEAUFORT_SCALE,
+                     |force| Cow::Owned(format!("{} - {}", force.number, force.description))) {
+                     let sea_state = beaufort::BEAUFORT_SCALE[beaufort_idx].sea_state();
+                     wind_speed = sea_state.wind_speed;
+                     let direction = wind_direction_deg.to_radians();
+                     ocean.set_wind(display, sea_state.wind(glam::vec2(direction.cos(), direction.sin())));
+                     spectrum_idx = beaufort_spectrum_idx(sea_state.spectrum);
+                     if let beaufort::BeaufortSpectrum::Jonswap { fetch_km } = sea_state.spectrum {
+                        jonswap_peak_enhancement = consts::JONSWAP_PEAK_ENHANCEMENT;
+                        jonswap_fetch_km = fetch_km;
+                     }
+                     switch_spectrum_for_depth(finite_water_depth, &mut spectrum_idx, &mut deep_water_spectrum_idx);
+                     ocean.set_spectrum(display, &|| make_wave_spectrum(
+                        spectrum_idx, jonswap_peak_enhancement, jonswap_fetch_km, water_depth));
+                     spectrum_amplitude = sea_state.spectrum_amplitude;
+                     ocean.set_spectrum_amplitude(display, spectrum_amplitude);
+                     water_choppiness = sea_state.choppiness;
+                     water.set_choppiness(water_choppiness);
+                     ocean.set_choppiness(water_choppiness);
+                     lighting.foam_threshold = sea_state.foam_threshold;
+                     lighting.foam_intensity = sea_state.foam_intensity;
+                     water.set_lighting(lighting);
+                  }
+                  ui.text(format!("Expected Hs: {:.2} m (probable in open sea: {:.1} m)",
+                     ocean.significant_wave_height(),
+                     beaufort::BEAUFORT_SCALE[beaufort_idx].probable_wave_height));
+
+                  let wind_changed = imgui::Slider::new("Wind speed (m/s)", 0.5, 40.0)
+                     .build(ui, &mut wind_speed)
+                     | direction_dial(ui, "Wind direction (deg)", &mut wind_direction_deg);
+                  if wind_changed {
+                     let direction = wind_direction_deg.to_radians();
+                     ocean.set_wind(display, wave::Wind::new(wind_speed,
+                        glam::vec2(direction.cos(), direction.sin())));
+                  }
+                  if imgui::Slider::new("Spectrum amplitude", 0.0, 5.0)
+                     .build(ui, &mut spectrum_amplitude) {
+                     ocean.set_spectrum_amplitude(display, spectrum_amplitude);
+                  }
+                  if ui.input_float("Wavelength cutoff (m)", &mut length_cutoff_meters).build() {
+                     length_cutoff_meters = length_cutoff_meters.max(0.0);
+                     ocean.set_length_cutoff(display, length_cutoff_meters);
+                  }
+
+                  // the bottom slows waves down, and attenuates them,
+                  // which only TMA spectrum accounts for
+                  let mut depth_changed = ui.checkbox("Finite water depth", &mut finite_water_depth);
+                  if finite_water_depth {
+                     depth_changed |= imgui::Slider::new("Depth (m)", 1.0, 200.0)
+                        .build(ui, &mut finite_water_depth_meters);
+                  }
+                  if depth_changed {
+                     water_depth = if finite_water_depth { finite_water_depth_meters } else { f32::INFINITY };
+                     ocean.set_water_depth(display, water_depth);
+                     switch_spectrum_for_depth(finite_water_depth, &mut spectrum_idx, &mut deep_water_spectrum_idx);
+                     if finite_water_depth {
+                        lighting.water_depth = water_depth;
+                        water.set_lighting(lighting);
+                     }
+                  }
+
+                  let mut spectrum_changed = depth_changed | ui.combo("Spectrum",
+                     &mut spectrum_idx, &spectrum_variants,
+                     |spectrum_name| Cow::Borrowed(spectrum_name));
+                  if spectrum_idx >= 2 {
+                     spectrum_changed |= ui.input_float(
+                        "Peak enhancement", &mut jonswap_peak_enhancement).build();
+                     spectrum_changed |= ui.input_float(
+                        "Fetch (km)", &mut jonswap_fetch_km).build();
+                     // fetch divides, and peak enhancement is raised to a fractional power
+                     jonswap_peak_enhancement = jonswap_peak_enhancement.max(0.01);
+                     jonswap_fetch_km = jonswap_fetch_km.max(0.01);
+                  }
+                  if spectrum_changed {
+                     ocean.set_spectrum(display, &|| make_wave_spectrum(
+                        spectrum_idx, jonswap_peak_enhancement, jonswap_fetch_km, water_depth));
+                  }
+
+                  let mut spreading_changed = ui.combo("Spreading",
+                     &mut spreading_idx, &spreading_variants,
+                     |spreading_name| Cow::Borrowed(spreading_name));
+                  if spreading_idx == 0 {
+                     spreading_changed |= imgui::Slider::new("Spreading exponent", 0.0, 64.0)
+                        .build(ui, &mut spreading_exponent);
+                  }
+                  if spreading_idx == 1 || spreading_idx == 2 {
+                     spreading_changed |= imgui::Slider::new("Swell", 0.0, 1.0)
+                        .build(ui, &mut spreading_swell);
+                  }
+                  if spreading_changed {
+                     ocean.set_spreading(display, &|| make_directional_spreading(
+                        spreading_idx, spreading_exponent, spreading_swell));
+                  }
+
+                  let mut seed_changed = ui.input_int("Seed", &mut seed).build();
+                  ui.same_line();
+                  if ui.button("Random") {
+                     seed = rand::random::<u16>() as i32;
+                     seed_changed = true;
+                  }
+                  if seed_changed {
+                     seed = seed.max(0);
+                     ocean.set_seed(display, seed as u64);
+                  }
+                  ui.text(format!("Expected significant wave height: {:.2} m",
+                     ocean.significant_wave_height()));
+
+                  if imgui::Slider::new("Choppiness", 0.0, 3.0)
+                     .build(ui, &mut water_choppiness) {
+                     water.set_choppiness(water_choppiness);
+                     ocean.set_choppiness(water_choppiness);
+                  }
+               }
+
+               if CollapsingHeader::new("Weather timeline").build(ui) {
+                  if imgui::Slider::new("Transition duration (sec)", 0.0, 30.0)
+                     .build(ui, &mut spectrum_transition_sec) {
+                     ocean.set_transition_duration(spectrum_transition_sec);
+                  }
+                  let blend = ocean.cascades()[0].spectrum_blend();
+                  if blend < 1.0 {
+                     ui.text(format!("Transition: {:.0}%", blend * 100.0));
+                  }
+                  let mut removed_keyframe = None;
+                  for (idx, keyframe) in wind_timeline.keyframes().iter().enumerate() {
+                     let _id = ui.push_id(idx as i32);
+                     ui.text(format!("{:.1} sec: {:.1} m/s, {:.0} deg",
+                        keyframe.time, keyframe.speed, keyframe.direction_deg));
+                     ui.same_line();
+                     if ui.small_button("Remove") {
+                        removed_keyframe = Some(idx);
+                     }
+                  }
+                  if let Some(idx) = removed_keyframe {
+                     wind_timeline.remove(idx);
+                  }
+                  ui.input_float("Keyframe time (sec)", &mut new_keyframe.time).build();
+                  imgui::Slider::new("Keyframe wind speed (m/s)", 0.5, 40.0)
+                     .build(ui, &mut new_keyframe.speed);
+                  direction_dial(ui, "Keyframe wind direction (deg)", &mut new_keyframe.direction_deg);
+                  if ui.button("Add keyframe") {
+                     new_keyframe.time = new_keyframe.time.max(0.0);
+                     wind_timeline.add(new_keyframe);
+                  }
+                  ui.same_line();
+                  if ui.button("Clear keyframes") {
+                     wind_timeline = weather::WindTimeline::default();
+                  }
+                  let mut playing = timeline_start_time.is_some();
+                  if ui.checkbox("Play timeline", &mut playing) {
+                     timeline_start_time = playing.then_some(height_field_time);
+                     applied_keyframe = None;
+                  }
+                  ui.same_line();
+                  ui.checkbox("Loop", &mut timeline_loop);
+                  if let Some(start_time) = timeline_start_time {
+                     ui.text(format!("Timeline: {:.1} of {:.1} sec",
+                        height_field_time - start_time, wind_timeline.duration()));
+                  }
+               }
+
+               if CollapsingHeader::new("Statistics").build(ui) {
+                  ui.text(format!("Statistics of cascade {} (see \"Preview cascade\")", preview_cascade_idx));
+                  if ui.button("Compute statistics") {
+                     let height_field = &ocean.cascades()[preview_cascade_idx as usize];
+                     sea_state = Some(statistics::SeaStateComparison::new(
+                        height_field, &height_field.read_displacement()));
+                  }
+                  ui.same_line();
+                  ui.checkbox("Update continuously", &mut sea_state_continuous);
+                  if let Some(statistics::SeaStateComparison { simulated, expected, .. }) = &sea_state {
+                     ui.text(format!("Significant wave height: {:.2} m (expected {:.2} m)",
+                        simulated.significant_wave_height, expected.significant_wave_height));
+                     ui.text(format!("Maximum wave height: {:.2} m (expected {:.2} m of {} waves)",
+                        simulated.max_wave_height, expected.max_wave_height, expected.wave_count));
+                     ui.text(format!("Mean zero-crossing period: {:.2} s (expected {:.2} s)",
+                        simulated.mean_zero_crossing_period, expected.mean_zero_crossing_period));
+                     ui.text(format!("Peak period: {:.2} s (expected {:.2} s)",
+                        simulated.peak_period, expected.peak_period));
+                     let scale_max = simulated.spectrum.iter().chain(expected.spectrum.iter())
+                        .fold(0.0_f32, |max, &energy| max.max(energy));
+                     let max_wavenumber = simulated.wavenumber_step * (simulated.spectrum.len() - 1) as f32;
+                     ui.plot_lines("Simulated", &simulated.spectrum[1..])
+                        .overlay_text(format!("Energy per wavenumber, 0 to {:.2} rad/m", max_wavenumber))
+                        .scale_min(0.0).scale_max(scale_max)
+                        .graph_size([0.0, 80.0])
+                        .build();
+                     ui.plot_lines("Expected", &expected.spectrum[1..])
+                        .scale_min(0.0).scale_max(scale_max)
+                        .graph_size([0.0, 80.0])
+                        .build();
+                  }
+               }
+
+               if CollapsingHeader::new("Lighting").build(ui) {
+                  let sun_moved = imgui::Slider::new("Sun azimuth", 0.0, 360.0)
+                     .build(ui, &mut sun_azimuth)
+                     | imgui::Slider::new("Sun elevation", -10.0, 90.0)
+                     .build(ui, &mut sun_elevation);
+                  if sun_moved {
+                     lighting.sun_direction = render::WaterLighting::sun_direction_from_angles(
+                        sun_azimuth, sun_elevation);
+                  }
+                  let lighting_changed = sun_moved
+                     | imgui::Slider::new("Sun intensity", 0.0, 20.0)
+                        .build(ui, &mut lighting.sun_intensity)
+                     | imgui::ColorEdit::new("Sun color", &mut lighting.sun_color).build(ui)
+                     | imgui::ColorEdit::new("Sky zenith", &mut lighting.sky_zenith_color).build(ui)
+                     | imgui::ColorEdit::new("Sky horizon", &mut lighting.sky_horizon_color).build(ui)
+                     | imgui::Slider::new("Roughness", 0.01, 1.0)
+                        .build(ui, &mut lighting.roughness)
+                     | imgui::ColorEdit::new("Scatter color", &mut lighting.scatter_color).build(ui)
+                     | imgui::ColorEdit::new("Seabed color", &mut lighting.seabed_color).build(ui)
+                     | ui.input_float3("Absorption (1/m)", &mut lighting.absorption).build()
+                     | imgui::Slider::new("Water depth (m)", 0.0, 200.0)
+                        .build(ui, &mut lighting.water_depth)
+                     | imgui::Slider::new("Subsurface scattering", 0.0, 2.0)
+                        .build(ui, &mut lighting.subsurface_scattering)
+                     | imgui::ColorEdit::new("Foam color", &mut lighting.foam_color).build(ui)
+                     | imgui::Slider::new("Foam threshold (Jacobian)", -1.0, 1.0)
+                        .build(ui, &mut lighting.foam_threshold)
+                     | imgui::Slider::new("Foam intensity", 0.0, 1.0)
+                        .build(ui, &mut lighting.foam_intensity);
+                  if lighting_changed {
+                     water.set_lighting(lighting);
+                     for body_renderer in body_renderers.iter_mut() {
+                        body_renderer.set_lighting(lighting);
+                     }
+                  }
+               }
+
+               if CollapsingHeader::new("Floating bodies").build(ui) {
+                  ui.combo("Shape", &mut body_shape_idx, &body_shape_variants,
+                     |shape_name| Cow::Borrowed(shape_name));
+                  match body_shape_idx {
+                     0 => { ui.input_float3("Size (m)", &mut body_box_size).build(); }
+                     1 => {
+                        ui.input_float("Radius (m)", &mut body_capsule_radius).build();
+                        ui.input_float("Length (m)", &mut body_capsule_length).build();
+                     }
+                     _ => { ui.input_text("OBJ file", &mut body_obj_path).build(); }
+                  }
+                  imgui::Slider::new("Density (kg/m^3)", 50.0, 1000.0)
+                     .build(ui, &mut body_density);
+                  ui.input_float2("Spawn point (m)", &mut body_spawn_point).build();
+                  if ui.button("Spawn body") {
+                     let hull = match body_shape_idx {
+                        0 => Ok(buoyancy::Hull::cuboid(glam::Vec3::from(body_box_size))),
+                        1 => Ok(buoyancy::Hull::capsule(body_capsule_radius, body_capsule_length, 24)),
+                        _ => buoyancy::Hull::from_obj(std::path::Path::new(&body_obj_path)),
+                     };
+                     match hull {
+                        Ok(hull) => {
+                           let color = body_colors[body_renderers.len() % body_colors.len()];
+                           let mut body_renderer = render::BodyRenderer::new(display, &hull, color);
+                           body_renderer.set_lighting(lighting);
+                           body_renderers.push(body_renderer);
+                           let (_, max) = hull.bounds();
+                           let position = glam::vec3(body_spawn_point[0], max.y + 1.0, body_spawn_point[1]);
+                           floating_bodies.bodies.push(buoyancy::RigidBody::new(hull, body_density, position));
+                        }
+                        Err(error) => log::error!("Failed to load hull {}: {}", body_obj_path, error),
+                     }
+                  }
+                  ui.same_line();
+                  if ui.button("Remove all") {
+                     floating_bodies.bodies.clear();
+                     body_renderers.clear();
+                  }
+                  let parameters = &mut floating_bodies.parameters;
+                  imgui::Slider::new("Drag coefficient", 0.0, 2.0)
+                     .build(ui, &mut parameters.drag_coefficient);
+                  imgui::Slider::new("Linear damping", 0.0, 2.0)
+                     .build(ui, &mut parameters.linear_damping);
+                  imgui::Slider::new("Angular damping", 0.0, 2.0)
+                     .build(ui, &mut parameters.angular_damping);
+                  for (idx, body) in floating_bodies.bodies.iter().enumerate() {
+                     let (yaw, pitch, roll) = body.orientation.to_euler(glam::EulerRot::YZX);
+                     ui.text(format!("Body {}: {:.0} kg, height {:.2} m, pitch {:.1}, roll {:.1}, yaw {:.1} deg",
+                        idx, body.mass, body.position.y, pitch.to_degrees(), roll.to_degrees(), yaw.to_degrees()));
+                  }
+               }
+
+               if CollapsingHeader::new("Buoys").build(ui) {
+                  ui.input_float2("Buoy position (m)", &mut buoy_position).build();
+                  if ui.button("Add buoy") {
+                     buoys.push(buoys::Buoy::new(glam::Vec2::from(buoy_position)));
+                  }
+                  ui.same_line();
+                  if ui.button("Clear records") {
+                     buoys.iter_mut().for_each(buoys::Buoy::clear);
+                  }
+                  imgui::Slider::new("Plot length (records)", 50, 2000)
+                     .build(ui, &mut buoy_plot_length);
+                  let mut removed_buoy = None;
+                  for (idx, buoy) in buoys.iter().enumerate() {
+                     let _id = ui.push_id(idx as i32);
+                     let latest = buoy.records().last().copied();
+                     ui.text(format!("Buoy {} at ({:.1}, {:.1}): {} records", idx,
+                        buoy.position.x, buoy.position.y, buoy.records().len()));
+                     ui.same_line();
+                     if ui.small_button("Remove") {
+                        removed_buoy = Some(idx);
+                     }
+                     let elevations = buoy.recent_elevations(buoy_plot_length as usize);
+                     let overlay = latest.map(|record| format!(
+                        "h {:.2} m, v {:.2} m/s, a {:.2} m/s^2",
+                        record.elevation, record.vertical_velocity, record.vertical_acceleration))
+                        .unwrap_or_default();
+                     ui.plot_lines("Elevation", &elevations)
+                        .overlay_text(overlay)
+                        .graph_size([0.0, 60.0])
+                        .build();
+                  }
+                  if let Some(idx) = removed_buoy {
+                     buoys.remove(idx);
+                  }
+                  ui.input_text("CSV file", &mut buoys_csv_path).build();
+                  if ui.button("Export CSV") {
+                     let path = std::path::Path::new(&buoys_csv_path);
+                     if let Some(directory) = path.parent() {
+                        let _ = std::fs::create_dir_all(directory);
+                     }
+                     match buoys::write_csv(path, &buoys) {
+                        Ok(rows) => log::info!("Exported {} buoy records to {}", rows, path.display()),
+                        Err(error) => log::error!("Failed to export buoy records: {}", error),
+                     }
+                  }
+               }
+
+               if CollapsingHeader::new("Export").build(ui) {
+                  imgui::Slider::new("Export cascade", 0, cascade_count - 1)
+                     .build(ui, &mut export_cascade_idx);
+                  export_cascade_idx = export_cascade_idx.min(cascade_count - 1);
+                  ui.input_text("Directory", &mut export_directory).build();
+                  if ui.combo("Heightmap format", &mut heightmap_format_idx, &heightmap_format_variants,
+                     |format_name| Cow::Borrowed(format_name)) {
+                     heightmap_export.format = match heightmap_format_idx {
+                        0 => export::HeightmapFormat::Png16,
+                        _ => export::HeightmapFormat::Tiff32,
+                     };
+                  }
+                  ui.input_float("Frame rate", &mut heightmap_export.frame_rate).build();
+                  ui.checkbox("Include x/z displacement", &mut heightmap_export.with_horizontal);
+
+                  // one period of the cascade is sampled on GPU, blocks until finished
+                  if ui.button("Export heightmaps") {
+                     let height_field = &ocean.cascades()[export_cascade_idx as usize];
+                     let result = export::export_heightmaps(
+                        std::path::Path::new(&export_directory), &heightmap_export,
+                        height_field.period(), height_field.physical_size(),
+                        |time| {
+                           height_field.compute_height_field_gpu(time);
+                           height_field.read_displacement()
+                        });
+                     match result {
+                        Ok(n_frames) => log::info!("Exported {} heightmaps to {}", n_frames, export_directory),
+                        Err(error) => log::error!("Failed to export heightmaps: {}", error),
+                     }
+                  }
+
+                  ui.input_int("Flipbook frames", &mut flipbook_frame_count).build();
+                  if ui.button("Export flipbook") {
+                     let height_field = &ocean.cascades()[export_cascade_idx as usize];
+                     let flipbook = export::FlipbookExport {
+                        frame_count: flipbook_frame_count.max(1) as usize,
+                        choppiness: water_choppiness,
+                     };
+                     let result = export::export_flipbook(
+                        std::path::Path::new(&export_directory), &flipbook,
+                        height_field.period(), height_field.physical_size(),
+                        |time| {
+                           height_field.compute_height_field_gpu(time);
+                           height_field.read_displacement()
+                        });
+                     match result {
+                        Ok(()) => log::info!("Exported flipbook to {}", export_directory),
+                        Err(error) => log::error!("Failed to export flipbook: {}", error),
+                     }
+                  }
+
+                  ui.input_float("Mesh time (sec)", &mut mesh_time_sec).build();
+                  ui.combo("Mesh format", &mut mesh_format_idx, &mesh_format_variants,
+                     |format_name| Cow::Borrowed(format_name));
+                  if ui.button("Export mesh") {
+                     let height_field = &ocean.cascades()[export_cascade_idx as usize];
+                     height_field.compute_height_field_gpu(mesh_time_sec);
+                     let mesh = mesh_export::SurfaceMesh::from_displacement(
+                        &height_field.read_displacement(), height_field.physical_size(), water_choppiness);
+                     let format = match mesh_format_idx {
+                        0 => mesh_export::MeshFormat::Obj,
+                        1 => mesh_export::MeshFormat::Ply,
+                        _ => mesh_export::MeshFormat::Gltf,
+                     };
+                     let path = std::path::Path::new(&export_directory)
+                        .join(format!("ocean_mesh.{}", format.extension()));
+                     let result = std::fs::create_dir_all(&export_directory).map_err(|error| error.into())
+                        .and_then(|_| mesh.write(&path, format));
+                     match result {
+                        Ok(()) => log::info!("Exported mesh to {}", path.display()),
+                        Err(error) => log::error!("Failed to export mesh: {}", error),
+                     }
+                  }
+
+                  ui.combo("Animation format", &mut animation_format_idx, &animation_format_variants,
+                     |format_name| Cow::Borrowed(format_name));
+                  ui.input_float("Animation frame rate", &mut animation_frame_rate).build();
+                  if ui.button("Export animation") {
+                     let height_field = &ocean.cascades()[export_cascade_idx as usize];
+                     let animation = mesh_export::AnimationExport {
+                        format: match animation_format_idx {
+                           0 => mesh_export::AnimationFormat::Pc2,
+                           _ => mesh_export::AnimationFormat::GltfMorphTargets,
+                        },
+                        frame_rate: animation_frame_rate,
+                        choppiness: water_choppiness,
+                     };
+                     let file_name = match animation.format {
+                        mesh_export::AnimationFormat::Pc2 => "ocean_animation.pc2",
+                        mesh_export::AnimationFormat::GltfMorphTargets => "ocean_animation.glb",
+                     };
+                     let path = std::path::Path::new(&export_directory).join(file_name);
+                     let result = std::fs::create_dir_all(&export_directory).map_err(|error| error.into())
+                        .and_then(|_| mesh_export::export_animation(&path, &animation,
+                           height_field.period(), height_field.physical_size(),
+                           |time| {
+                              height_field.compute_height_field_gpu(time);
+                              height_field.read_displacement()
+                           }));
+                     match result {
+                        Ok(n_frames) => log::info!("Exported {} frames of animation to {}", n_frames, path.display()),
+                        Err(error) => log::error!("Failed to export animation: {}", error),
+                     }
                   }
                }
          });
@@ -186,10 +914,65 @@ fn main() {
             .elapsed().unwrap()
             .as_secs_f32();
 
-         height_field.compute_height_field_gpu(total_time);
+         // the wind is changed only when it differs noticeably, every change
+         // regenerates the spectrum, and it fades in anyway
+         // The last keyframe and the start of a loop are applied exactly,
+         // so the wind doesn't stay off by less than a step
+         if let Some(start_time) = timeline_start_time {
+            let mut timeline_time = total_time - start_time;
+            let duration = wind_timeline.duration();
+            if timeline_loop && duration > 0.0 {
+               timeline_time = timeline_time.rem_euclid(duration);
+            }
+            if let Some(keyframe) = wind_timeline.at(timeline_time) {
+               let changed = applied_keyframe.is_none_or(|applied|
+                  (keyframe.speed - applied.speed).abs() > consts::TIMELINE_WIND_SPEED_STEP
+                  || ((keyframe.direction_deg - applied.direction_deg + 180.0).rem_euclid(360.0) - 180.0).abs()
+                     > consts::TIMELINE_WIND_DIRECTION_STEP_DEG
+                  || (keyframe.time >= duration && applied.time < duration)
+                  || keyframe.time < applied.time);
+               if changed {
+                  ocean.set_wind(display, keyframe.wind());
+                  wind_speed = keyframe.speed;
+                  wind_direction_deg = keyframe.direction_deg;
+                  applied_keyframe = Some(keyframe);
+               }
+            }
+         }
+
+         ocean.advance_spectrum_transitions(total_time);
+         ocean.compute_height_field_gpu(total_time);
+         height_field_time = total_time;
+         // floating bodies are moved by the surface from a few frames ago,
+         // so that the render loop doesn't wait for the GPU
+         if async_readback || sea_state_continuous
+            || !floating_bodies.bodies.is_empty() || !buoys.is_empty() {
+            ocean.request_displacement_readback(total_time);
+         }
+         if sea_state_continuous {
+            let height_field = &ocean.cascades()[preview_cascade_idx as usize];
+            if let Some(latest) = height_field.latest_displacement() {
+               match sea_state.as_mut() {
+                  Some(sea_state) => sea_state.update(height_field, &latest.grid),
+                  None => sea_state = Some(statistics::SeaStateComparison::new(height_field, &latest.grid)),
+               }
+            }
+         }
+         if let Some(surface) = ocean.latest_surface() {
+            floating_bodies.step(frame_time_sec, |world_xz| surface.sample(water.world_to_grid(world_xz)).height);
+            for buoy in buoys.iter_mut() {
+               buoy.record(surface.time(), surface.sample(water.world_to_grid(buoy.position)));
+            }
+         }
          
          water.set_draw_mode(draw_mode);
-         water.draw_to(frame, &camera);
+         water.draw_displaced_to(frame, &camera, &ocean.surface_maps());
+         for (body, body_renderer) in floating_bodies.bodies.iter().zip(body_renderers.iter_mut()) {
+            body_renderer.set_model(body.transform());
+            body_renderer.draw_to(frame, &camera);
+         }
+
+         let height_field = &ocean.cascades()[preview_cascade_idx as usize];
 
          let (window_w, window_h) = display.get_framebuffer_dimensions();
          let blit_width_px = debug_textures_size as u32;
@@ -224,7 +1007,6 @@ fn main() {
                blit_width_px, blit_width_px);
             blitter.set_texture(height_field.base_spectrum_conjugate());
             blitter.draw_to(frame, &camera);
-            free_slot_x += 1;
          }
          if show_spectrum_realization_textures {
             free_slot_x = 1;
@@ -249,7 +1031,6 @@ fn main() {
                blit_width_px, blit_width_px);
             blitter.set_texture(height_field.spectrum_realization_dz());
             blitter.draw_to(frame, &camera);
-            free_slot_x += 1;
          }
          if show_height_field_texture {
             free_slot_x = 1;
@@ -268,6 +1049,109 @@ fn main() {
             blitter.set_texture(height_field.previous_height_field());
             blitter.draw_to(frame, &camera);
             free_slot_x += 1;
+            let mut blitter = render::TextureBlitter::new(
+               window_w-(blit_width_px+blit_offset_px)*free_slot_x, 
+               window_h-(blit_width_px+blit_offset_px)*free_slot_y,
+               blit_width_px, blit_width_px);
+            blitter.set_texture(height_field.displacement_map());
+            blitter.draw_to(frame, &camera);
+         }
+         if show_surface_textures {
+            free_slot_x = 1;
+            free_slot_y += 1;
+            let mut blitter = render::TextureBlitter::new(
+               window_w-(blit_width_px+blit_offset_px)*free_slot_x, 
+               window_h-(blit_width_px+blit_offset_px)*free_slot_y,
+               blit_width_px, blit_width_px);
+            blitter.set_texture(height_field.normal_map());
+            blitter.draw_to(frame, &camera);
+            free_slot_x += 1;
+            let mut blitter = render::TextureBlitter::new(
+               window_w-(blit_width_px+blit_offset_px)*free_slot_x, 
+               window_h-(blit_width_px+blit_offset_px)*free_slot_y,
+               blit_width_px, blit_width_px);
+            blitter.set_texture(height_field.jacobian_map());
+            blitter.draw_to(frame, &camera);
          }
    });
 }
+
+// The largest cascade matches the rendered mesh, the others are smaller
+fn default_cascade_sizes(mesh_size: f32) -> [f32; consts::MAX_CASCADES] {
+   let mut cascade_sizes = [0.0; consts::MAX_CASCADES];
+   for (idx, cascade_size) in cascade_sizes.iter_mut().enumerate() {
+      *cascade_size = mesh_size / consts::CASCADE_SIZE_RATIO.powi(idx as i32);
+   }
+   cascade_sizes
+}
+
+// Spectrum models in the same order, as in "Spectrum" combo box
+fn make_wave_spectrum(spectrum_idx: usize, peak_enhancement: f32, fetch_km: f32, depth: f32) -> Box<dyn wave::WaveSpectrum> {
+   let jonswap = wave::JonswapSpectrum {
+      peak_enhancement, fetch: fetch_km * 1000.0,
+   };
+   match spectrum_idx {
+      0 => Box::new(wave::PhillipsSpectrum { amplitude: consts::PHILLIPS_SPECTRUM_AMPLITUDE }),
+      1 => Box::new(wave::PiersonMoskowitzSpectrum),
+      2 => Box::new(jonswap),
+      _ => Box::new(wave::TmaSpectrum { jonswap, depth }),
+   }
+}
+
+fn beaufort_spectrum_idx(spectrum: beaufort::BeaufortSpectrum) -> usize {
+   match spectrum {
+      beaufort::BeaufortSpectrum::PiersonMoskowitz => 1,
+      beaufort::BeaufortSpectrum::Jonswap { .. } => 2,
+   }
+}
+
+// Finite depth needs TMA spectrum, the spectrum chosen for deep water
+// is remembered, and it's back once the water is deep again
+fn switch_spectrum_for_depth(finite_water_depth: bool, spectrum_idx: &mut usize, deep_water_spectrum_idx: &mut usize) {
+   if finite_water_depth {
+      if *spectrum_idx != 3 {
+         *deep_water_spectrum_idx = *spectrum_idx;
+      }
+      *spectrum_idx = 3;
+   } else if *spectrum_idx == 3 {
+      *spectrum_idx = *deep_water_spectrum_idx;
+   }
+}
+
+// Dial with an arrow, that is dragged around by mouse, followed by a slider
+// Angle is measured from X axis towards Z axis (screen up is +X, right is +Z,
+// like in the top-down view)
+fn direction_dial(ui: &imgui::Ui, label: &str, degrees: &mut f32) -> bool {
+   let radius = 24.0;
+   let corner = ui.cursor_screen_pos();
+   let center = [corner[0] + radius, corner[1] + radius];
+   let _id = ui.push_id(label);
+   ui.invisible_button("dial", [2.0 * radius, 2.0 * radius]);
+   let mut changed = false;
+   if ui.is_item_active() {
+      let mouse = ui.io().mouse_pos;
+      let (right, up) = (mouse[0] - center[0], center[1] - mouse[1]);
+      if right != 0.0 || up != 0.0 {
+         *degrees = f32::atan2(right, up).to_degrees().rem_euclid(360.0);
+         changed = true;
+      }
+   }
+   let angle = degrees.to_radians();
+   let tip = [center[0] + radius * angle.sin(), center[1] - radius * angle.cos()];
+   let draw_list = ui.get_window_draw_list();
+   draw_list.add_circle(center, radius, [0.6, 0.6, 0.6, 1.0]).build();
+   draw_list.add_line(center, tip, [1.0, 0.8, 0.2, 1.0]).thickness(2.0).build();
+   ui.same_line();
+   changed |= imgui::Slider::new(label, 0.0, 360.0).build(ui, degrees);
+   changed
+}
+
+// Spreading models in the same order, as in "Spreading" combo box
+fn make_directional_spreading(spreading_idx: usize, exponent: f32, swell: f32) -> Box<dyn wave::DirectionalSpreading> {
+   match spreading_idx {
+      0 => Box::new(wave::Cos2sSpreading { exponent }),
+      1 => Box::new(wave::MitsuyasuSpreading { swell }),
+      2 => Box::new(wave::HasselmannSpreading { swell }),
+      _ => Box::new(wave::DonelanBannerSpreading),
+   }
+}
@@ -0,0 +1,336 @@
+// Export of the displaced water surface as a triangle mesh
+// (OBJ, binary PLY or binary glTF 2.0), with positions, normals and UVs,
+// and its animation over one period (PC2 point cache or glTF morph targets)
+// The mesh is the indexed grid from mesh_grid::make_indexed_grid,
+// it covers exactly one tile of the height field: vertices on the far
+// borders repeat displacement of the near borders, so the tiles connect
+// All formats keep the same vertex order
+
+use crate::export::{ExportResult, frame_count, frame_times};
+use crate::height_field_cpu::DisplacementGrid;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum MeshFormat {
+   Obj, Ply, Gltf,
+}
+
+impl MeshFormat {
+   pub fn extension(&self) -> &'static str {
+      match self {
+         MeshFormat::Obj => "obj",
+         MeshFormat::Ply => "ply",
+         MeshFormat::Gltf => "glb",
+      }
+   }
+
+   pub fn from_path(path: &Path) -> Option<Self> {
+      match path.extension()?.to_str()? {
+         "obj" => Some(MeshFormat::Obj),
+         "ply" => Some(MeshFormat::Ply),
+         "glb" => Some(MeshFormat::Gltf),
+         _ => None,
+      }
+   }
+}
+
+pub struct SurfaceMesh {
+   pub positions: Vec<glam::Vec3>,
+   pub normals: Vec<glam::Vec3>,
+   pub uvs: Vec<glam::Vec2>, // from 0 to 1 over the tile
+   pub indices: Vec<u32>, // triangle list
+}
+
+impl SurfaceMesh {
+   // Grid of (size+1) x (size+1) vertices, one per texel of the height field
+   // Displaced positions are (x - choppiness*dx, dy, z - choppiness*dz), as in "mesh_grid.vert"
+   pub fn from_displacement(grid: &DisplacementGrid, physical_size: f32, choppiness: f32) -> Self {
+      let size = grid.size;
+      let (vertices, indices) = crate::mesh_grid::make_indexed_grid(
+         (size as u32, size as u32), physical_size / size as f32);
+      let grid_normals = grid.normals(physical_size, choppiness);
+      Self {
+         positions: Self::displaced_positions(grid, physical_size, choppiness),
+         normals: (0..vertices.len())
+            .map(|vertex_idx| grid_normals[Self::grid_index(vertex_idx, size)])
+            .collect(),
+         uvs: vertices.iter()
+            .map(|vertex| glam::Vec2::from(vertex.position) / physical_size)
+            .collect(),
+         indices,
+      }
+   }
+
+   // Only vertex positions of the mesh, e.g. for every frame of animation
+   pub fn displaced_positions(grid: &DisplacementGrid, physical_size: f32, choppiness: f32) -> Vec<glam::Vec3> {
+      let size = grid.size;
+      let cell_size = physical_size / size as f32;
+      (0..(size + 1)*(size + 1)).map(|vertex_idx| {
+         let (row, col) = (vertex_idx / (size + 1), vertex_idx % (size + 1));
+         let idx = Self::grid_index(vertex_idx, size);
+         glam::vec3(col as f32 * cell_size - choppiness * grid.dx[idx],
+            grid.dy[idx],
+            row as f32 * cell_size - choppiness * grid.dz[idx])
+      }).collect()
+   }
+
+   // Index of grid value for vertex of the (size+1) x (size+1) mesh
+   fn grid_index(vertex_idx: usize, size: usize) -> usize {
+      let (row, col) = (vertex_idx / (size + 1), vertex_idx % (size + 1));
+      (row % size)*size + col % size
+   }
+
+   pub fn write(&self, path: &Path, format: MeshFormat) -> ExportResult<()> {
+      match format {
+         MeshFormat::Obj => self.write_obj(path),
+         MeshFormat::Ply => self.write_ply(path),
+         MeshFormat::Gltf => self.write_glb(path),
+      }
+   }
+
+   fn write_obj(&self, path: &Path) -> ExportResult<()> {
+      let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+      for position in self.positions.iter() {
+         writeln!(file, "v {} {} {}", position.x, position.y, position.z)?;
+      }
+      for normal in self.normals.iter() {
+         writeln!(file, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+      }
+      for uv in self.uvs.iter() {
+         writeln!(file, "vt {} {}", uv.x, uv.y)?;
+      }
+      // OBJ indices start from 1
+      for triangle in self.indices.chunks(3) {
+         let (a, b, c) = (triangle[0] + 1, triangle[1] + 1, triangle[2] + 1);
+         writeln!(file, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}", a = a, b = b, c = c)?;
+      }
+      Ok(())
+   }
+
+   fn write_ply(&self, path: &Path) -> ExportResult<()> {
+      let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+      write!(file, "ply\nformat binary_little_endian 1.0\n\
+         element vertex {}\n\
+         property float x\nproperty float y\nproperty float z\n\
+         property float nx\nproperty float ny\nproperty float nz\n\
+         property float s\nproperty float t\n\
+         element face {}\n\
+         property list uchar uint vertex_indices\n\
+         end_header\n", self.positions.len(), self.indices.len() / 3)?;
+      for idx in 0..self.positions.len() {
+         let (position, normal, uv) = (self.positions[idx], self.normals[idx], self.uvs[idx]);
+         for value in [position.x, position.y, position.z, normal.x, normal.y, normal.z, uv.x, uv.y] {
+            file.write_all(&value.to_le_bytes())?;
+         }
+      }
+      for triangle in self.indices.chunks(3) {
+         file.write_all(&[3u8])?;
+         for index in triangle {
+            file.write_all(&index.to_le_bytes())?;
+         }
+      }
+      Ok(())
+   }
+
+   fn write_glb(&self, path: &Path) -> ExportResult<()> {
+      let mut gltf = GltfBuilder::default();
+      let position = gltf.add_vec3(&self.positions);
+      let normal = gltf.add_vec3(&self.normals);
+      let uv = gltf.add_vec2(&self.uvs);
+      let indices = gltf.add_indices(&self.indices);
+      let primitive = format!(
+         "{{\"attributes\":{{\"POSITION\":{},\"NORMAL\":{},\"TEXCOORD_0\":{}}},\"indices\":{},\"mode\":4}}",
+         position, normal, uv, indices);
+      gltf.write_glb(path, &format!("\"meshes\":[{{\"primitives\":[{}]}}]", primitive), None)
+   }
+}
+
+// PC2 - point cache with positions of all vertices for every frame,
+//   the base mesh (first frame) is written next to it as OBJ
+// glTF - the first frame is the base mesh, the other frames are morph targets,
+//   the animation switches weights between them (looping back to the base)
+#[derive(Copy, Clone, PartialEq)]
+pub enum AnimationFormat {
+   Pc2, GltfMorphTargets,
+}
+
+pub struct AnimationExport {
+   pub format: AnimationFormat,
+   pub frame_rate: f32,
+   pub choppiness: f32,
+}
+
+// Returns the number of written frames
+pub fn export_animation(path: &Path, options: &AnimationExport, period_sec: f32, physical_size: f32,
+   mut sample: impl FnMut(f32) -> DisplacementGrid) -> ExportResult<usize> {
+   let times = frame_times(period_sec, frame_count(period_sec, options.frame_rate)?)?;
+   let base_grid = sample(times[0]);
+   let base_mesh = SurfaceMesh::from_displacement(&base_grid, physical_size, options.choppiness);
+   let mut frame_positions = times.iter().skip(1).map(|&time|
+      SurfaceMesh::displaced_positions(&sample(time), physical_size, options.choppiness));
+   match options.format {
+      AnimationFormat::Pc2 => {
+         base_mesh.write(&path.with_extension("obj"), MeshFormat::Obj)?;
+         write_pc2(path, &base_mesh.positions, frame_positions, times.len())?;
+      }
+      AnimationFormat::GltfMorphTargets =>
+         write_morph_targets_glb(path, &base_mesh, &mut frame_positions, &times, period_sec)?,
+   }
+   Ok(times.len())
+}
+
+// Frames are streamed into the file, without keeping all of them in memory
+fn write_pc2(path: &Path, base_positions: &[glam::Vec3], frame_positions: impl Iterator<Item = Vec<glam::Vec3>>,
+   n_frames: usize) -> ExportResult<()> {
+   let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+   file.write_all(b"POINTCACHE2\0")?;
+   file.write_all(&1i32.to_le_bytes())?; // file version
+   file.write_all(&(base_positions.len() as i32).to_le_bytes())?;
+   file.write_all(&0.0f32.to_le_bytes())?; // start frame
+   file.write_all(&1.0f32.to_le_bytes())?; // sampling interval (in frames)
+   file.write_all(&(n_frames as i32).to_le_bytes())?;
+
+   let mut write_frame = |positions: &[glam::Vec3]| -> std::io::Result<()> {
+      for position in positions {
+         for value in position.to_array() {
+            file.write_all(&value.to_le_bytes())?;
+         }
+      }
+      Ok(())
+   };
+   write_frame(base_positions)?;
+   for positions in frame_positions {
+      write_frame(&positions)?;
+   }
+   Ok(())
+}
+
+fn write_morph_targets_glb(path: &Path, base_mesh: &SurfaceMesh,
+   frame_positions: &mut impl Iterator<Item = Vec<glam::Vec3>>, times: &[f32], period_sec: f32) -> ExportResult<()> {
+   // without morph targets the animation has no weights to switch
+   if times.len() < 2 {
+      return Err(format!("Animation needs at least 2 frames, got {} (raise the frame rate)", times.len()).into());
+   }
+   let mut gltf = GltfBuilder::default();
+   let position = gltf.add_vec3(&base_mesh.positions);
+   let normal = gltf.add_vec3(&base_mesh.normals);
+   let uv = gltf.add_vec2(&base_mesh.uvs);
+   let indices = gltf.add_indices(&base_mesh.indices);
+
+   // morph targets store offsets from the base positions
+   let targets = frame_positions.map(|positions| {
+      let offsets = positions.iter().zip(base_mesh.positions.iter())
+         .map(|(position, base)| *position - *base)
+         .collect::<Vec<_>>();
+      format!("{{\"POSITION\":{}}}", gltf.add_vec3(&offsets))
+   }).collect::<Vec<_>>();
+   let n_targets = targets.len();
+
+   // keyframe i has only weight of target i-1 (none for the base frame),
+   // the extra keyframe at the end of period returns to the base frame
+   let mut keyframe_times = times.to_vec();
+   keyframe_times.push(period_sec);
+   let weights = (0..keyframe_times.len()).flat_map(|keyframe| (0..n_targets).map(move |target|
+      if keyframe == target + 1 { 1.0 } else { 0.0 }
+   )).collect::<Vec<f32>>();
+   let input = gltf.add_scalars(&keyframe_times);
+   let output = gltf.add_scalars(&weights);
+
+   let primitive = format!(
+      "{{\"attributes\":{{\"POSITION\":{},\"NORMAL\":{},\"TEXCOORD_0\":{}}},\"indices\":{},\"mode\":4,\"targets\":[{}]}}",
+      position, normal, uv, indices, targets.join(","));
+   let meshes = format!("\"meshes\":[{{\"primitives\":[{}],\"weights\":[{}]}}]",
+      primitive, vec!["0"; n_targets].join(","));
+   let animations = format!(
+      "\"animations\":[{{\"channels\":[{{\"sampler\":0,\"target\":{{\"node\":0,\"path\":\"weights\"}}}}],\
+      \"samplers\":[{{\"input\":{},\"output\":{},\"interpolation\":\"LINEAR\"}}]}}]",
+      input, output);
+   gltf.write_glb(path, &meshes, Some(&animations))
+}
+
+// Minimal writer of binary glTF 2.0 (.glb): all data goes into one binary buffer,
+// every added array gets its own buffer view and accessor (their index is returned)
+#[derive(Default)]
+pub struct GltfBuilder {
+   buffer: Vec<u8>,
+   buffer_views: Vec<String>,
+   accessors: Vec<String>,
+}
+
+impl GltfBuilder {
+   const FLOAT: u32 = 5126;
+   const UNSIGNED_INT: u32 = 5125;
+   const ARRAY_BUFFER: u32 = 34962;
+   const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+   // POSITION accessors require bounds, so they're always written for VEC3
+   pub fn add_vec3(&mut self, values: &[glam::Vec3]) -> usize {
+      let min = values.iter().fold(glam::Vec3::splat(f32::MAX), |min, &value| min.min(value));
+      let max = values.iter().fold(glam::Vec3::splat(f32::MIN), |max, &value| max.max(value));
+      let data = values.iter().flat_map(|value| value.to_array()).flat_map(f32::to_le_bytes).collect::<Vec<_>>();
+      self.add_accessor(&data, Self::FLOAT, Some(Self::ARRAY_BUFFER), values.len(), "VEC3",
+         &format!(",\"min\":[{},{},{}],\"max\":[{},{},{}]", min.x, min.y, min.z, max.x, max.y, max.z))
+   }
+
+   pub fn add_vec2(&mut self, values: &[glam::Vec2]) -> usize {
+      let data = values.iter().flat_map(|value| value.to_array()).flat_map(f32::to_le_bytes).collect::<Vec<_>>();
+      self.add_accessor(&data, Self::FLOAT, Some(Self::ARRAY_BUFFER), values.len(), "VEC2", "")
+   }
+
+   // e.g. keyframe times and weights of animations (bounds are required for times)
+   pub fn add_scalars(&mut self, values: &[f32]) -> usize {
+      let min = values.iter().copied().fold(f32::MAX, f32::min);
+      let max = values.iter().copied().fold(f32::MIN, f32::max);
+      let data = values.iter().copied().flat_map(f32::to_le_bytes).collect::<Vec<_>>();
+      self.add_accessor(&data, Self::FLOAT, None, values.len(), "SCALAR",
+         &format!(",\"min\":[{}],\"max\":[{}]", min, max))
+   }
+
+   pub fn add_indices(&mut self, indices: &[u32]) -> usize {
+      let data = indices.iter().copied().flat_map(u32::to_le_bytes).collect::<Vec<_>>();
+      self.add_accessor(&data, Self::UNSIGNED_INT, Some(Self::ELEMENT_ARRAY_BUFFER), indices.len(), "SCALAR", "")
+   }
+
+   // All components are 4-byte, so buffer views stay aligned
+   fn add_accessor(&mut self, data: &[u8], component_type: u32, target: Option<u32>,
+      count: usize, accessor_type: &str, extra: &str) -> usize {
+      let byte_offset = self.buffer.len();
+      self.buffer.extend_from_slice(data);
+      let target = target.map(|target| format!(",\"target\":{}", target)).unwrap_or_default();
+      self.buffer_views.push(format!("{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{}{}}}",
+         byte_offset, data.len(), target));
+      self.accessors.push(format!("{{\"bufferView\":{},\"componentType\":{},\"count\":{},\"type\":\"{}\"{}}}",
+         self.buffer_views.len() - 1, component_type, count, accessor_type, extra));
+      self.accessors.len() - 1
+   }
+
+   // Scene with a single node of mesh 0, "meshes" (and optionally "animations")
+   // are JSON properties written by the caller
+   pub fn write_glb(&self, path: &Path, meshes: &str, animations: Option<&str>) -> ExportResult<()> {
+      let animations = animations.map(|animations| format!(",{}", animations)).unwrap_or_default();
+      let mut json = format!(
+         "{{\"asset\":{{\"version\":\"2.0\",\"generator\":\"fft-water\"}},\
+         \"scene\":0,\"scenes\":[{{\"nodes\":[0]}}],\"nodes\":[{{\"mesh\":0}}],{}{},\
+         \"buffers\":[{{\"byteLength\":{}}}],\"bufferViews\":[{}],\"accessors\":[{}]}}",
+         meshes, animations, self.buffer.len(), self.buffer_views.join(","), self.accessors.join(","))
+         .into_bytes();
+      // chunks are 4-byte aligned, JSON is padded with spaces
+      while json.len() % 4 != 0 {
+         json.push(b' ');
+      }
+      let total_length = 12 + 8 + json.len() + 8 + self.buffer.len();
+
+      let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+      file.write_all(b"glTF")?;
+      file.write_all(&2u32.to_le_bytes())?;
+      file.write_all(&(total_length as u32).to_le_bytes())?;
+      file.write_all(&(json.len() as u32).to_le_bytes())?;
+      file.write_all(b"JSON")?;
+      file.write_all(&json)?;
+      file.write_all(&(self.buffer.len() as u32).to_le_bytes())?;
+      file.write_all(b"BIN\0")?;
+      file.write_all(&self.buffer)?;
+      Ok(())
+   }
+}
@@ -0,0 +1,264 @@
+// OceanCascades - several HeightField instances of different physical sizes
+// A single height field either tiles visibly (when small) or lacks small
+// detail (when large), so the largest cascade gives long waves and hides
+// tiling of the smaller ones, while the smallest cascades give fine detail
+// Each wavevector magnitude is simulated by exactly one cascade
+// (wavenumber bands don't overlap), so the energy isn't counted twice,
+// and displacements of all cascades can be simply summed up
+
+use crate::consts;
+use crate::height_field::{HeightField, SeaState};
+use crate::height_field_cpu::Displacement;
+use crate::readback::ReadbackFrame;
+use crate::render::SurfaceMaps;
+use crate::wave::{Wind, WaveSpectrum, DirectionalSpreading};
+use glium::Display;
+
+// Spectrum models are owned by every cascade, so new ones are created on demand
+pub type SpectrumFactory<'f> = &'f dyn Fn() -> Box<dyn WaveSpectrum>;
+pub type SpreadingFactory<'f> = &'f dyn Fn() -> Box<dyn DirectionalSpreading>;
+
+pub struct OceanCascades {
+   // size of computing domain of every cascade, has to be a power of 2
+   lattice_size: usize,
+   period_sec: f32,
+   seed: u64,
+   choppiness: f32,
+   wind: Wind,
+   spectrum_amplitude: f32,
+   length_cutoff_meters: f32,
+   transition_duration_sec: f32,
+   water_depth: f32,
+   cascades: Vec<HeightField>,
+}
+
+// Displacement maps of all cascades, as they were most recently copied
+// to CPU memory (see request_displacement_readback)
+pub struct OceanSurface<'o> {
+   cascades: Vec<(std::cell::Ref<'o, ReadbackFrame>, f32)>,
+   choppiness: f32,
+}
+
+impl<'o> OceanSurface<'o> {
+   // Sum of all cascades, that is right at the given point
+   // (in coordinates of height fields, see HeightField::sample)
+   pub fn sample(&self, xz: glam::Vec2) -> Displacement {
+      Displacement::at_displaced_point(xz, self.choppiness, |xz| {
+         self.cascades.iter().fold(Displacement::default(), |sum, (frame, physical_size)|
+            sum + frame.grid.sample_bilinear(xz, *physical_size))
+      })
+   }
+
+   // Simulation time of the surface (cascades are copied together,
+   // but may lag behind each other, if some copy was skipped)
+   pub fn time(&self) -> f32 {
+      self.cascades.iter().map(|(frame, _)| frame.time).fold(f32::MAX, f32::min)
+   }
+}
+
+impl OceanCascades {
+   pub fn new(display: &Display, lattice_size: usize, physical_sizes: &[f32], period_sec: f32,
+      make_spectrum: SpectrumFactory, make_spreading: SpreadingFactory) -> Self {
+      let mut instance = Self {
+         lattice_size,
+         period_sec,
+         seed: consts::RANDOM_SEED,
+         choppiness: consts::CHOPPINESS,
+         wind: Wind::new(consts::WIND_VELOCITY,
+            glam::vec2(consts::WIND_DIRECTION_X, consts::WIND_DIRECTION_Y)),
+         spectrum_amplitude: consts::SPECTRUM_AMPLITUDE,
+         length_cutoff_meters: consts::WAVELENGTH_CUTOFF_METERS,
+         transition_duration_sec: consts::SPECTRUM_TRANSITION_SEC,
+         water_depth: f32::INFINITY,
+         cascades: Vec::new(),
+      };
+      instance.set_physical_sizes(display, lattice_size, physical_sizes, make_spectrum, make_spreading);
+      instance
+   }
+
+   // Existing cascades are resized, missing ones are created, extra ones are dropped
+   // (all of them get the new lattice size)
+   pub fn set_physical_sizes(&mut self, display: &Display, lattice_size: usize, physical_sizes: &[f32],
+      make_spectrum: SpectrumFactory, make_spreading: SpreadingFactory) {
+      self.lattice_size = lattice_size;
+      assert!((1..=consts::MAX_CASCADES).contains(&physical_sizes.len()),
+         "Number of cascades should be from 1 to {}", consts::MAX_CASCADES);
+      // bands are known in advance, so every spectrum is built only once
+      let bands = Self::wavenumber_bands(lattice_size, physical_sizes);
+      self.cascades.truncate(physical_sizes.len());
+      for ((cascade, &physical_size), &band) in self.cascades.iter_mut().zip(physical_sizes).zip(&bands) {
+         cascade.regenerate_textures(display, self.lattice_size, physical_size, band);
+      }
+      for idx in self.cascades.len()..physical_sizes.len() {
+         let sea_state = SeaState {
+            wind: self.wind,
+            spectrum_amplitude: self.spectrum_amplitude,
+            length_cutoff_meters: self.length_cutoff_meters,
+            water_depth: self.water_depth,
+            seed: self.cascade_seed(idx),
+            wavenumber_band: bands[idx],
+         };
+         let mut cascade = HeightField::new(display, self.lattice_size, physical_sizes[idx],
+            self.period_sec, sea_state, make_spectrum(), make_spreading());
+         cascade.set_choppiness(self.choppiness);
+         cascade.set_transition_duration(self.transition_duration_sec);
+         self.cascades.push(cascade);
+      }
+   }
+
+   // Every cascade simulates wavevectors from its band:
+   // the largest cascade starts from zero, the smallest one is limited only
+   // by its own resolution, and in between the boundary is a few of the
+   // longest waves that fit into the smaller cascade (but still resolved
+   // by the larger one)
+   fn wavenumber_bands(lattice_size: usize, physical_sizes: &[f32]) -> Vec<(f32, f32)> {
+      let mut order = (0..physical_sizes.len()).collect::<Vec<_>>();
+      order.sort_by(|&a, &b| physical_sizes[b].total_cmp(&physical_sizes[a]));
+
+      let boundaries = order.windows(2).map(|pair| {
+         let larger_size = physical_sizes[pair[0]];
+         let smaller_size = physical_sizes[pair[1]];
+         let nyquist_wavenumber = consts::PI * lattice_size as f32 / larger_size;
+         f32::min(consts::CASCADE_BAND_FACTOR * 2.0 * consts::PI / smaller_size, nyquist_wavenumber)
+      }).collect::<Vec<_>>();
+
+      let mut bands = vec![(0.0, f32::INFINITY); physical_sizes.len()];
+      for (rank, &idx) in order.iter().enumerate() {
+         let min_wavenumber = if rank == 0 { 0.0 } else { boundaries[rank - 1] };
+         let max_wavenumber = boundaries.get(rank).copied().unwrap_or(f32::INFINITY);
+         bands[idx] = (min_wavenumber, max_wavenumber);
+      }
+      bands
+   }
+
+   // See HeightField::advance_spectrum_transition
+   pub fn advance_spectrum_transitions(&mut self, clock_sec: f32) {
+      for cascade in self.cascades.iter_mut() {
+         cascade.advance_spectrum_transition(clock_sec);
+      }
+   }
+
+   pub fn compute_height_field_gpu(&self, time: f32) {
+      for cascade in self.cascades.iter() {
+         cascade.compute_height_field_gpu(time);
+      }
+   }
+
+   // Non-blocking copy of displacement maps to CPU memory (see AsyncReadback)
+   pub fn request_displacement_readback(&self, time: f32) {
+      for cascade in self.cascades.iter() {
+         cascade.request_displacement_readback(time);
+      }
+   }
+
+   // None until every cascade has finished its first copy
+   pub fn latest_surface(&self) -> Option<OceanSurface<'_>> {
+      let cascades = self.cascades.iter()
+         .map(|cascade| cascade.latest_displacement().map(|frame| (frame, cascade.physical_size())))
+         .collect::<Option<Vec<_>>>()?;
+      Some(OceanSurface { cascades, choppiness: self.choppiness })
+   }
+
+   pub fn set_readback_buffer_count(&mut self, buffer_count: usize) {
+      for cascade in self.cascades.iter_mut() {
+         cascade.set_readback_buffer_count(buffer_count);
+      }
+   }
+
+   pub fn set_period(&mut self, period_sec: f32) {
+      self.period_sec = period_sec;
+      for cascade in self.cascades.iter_mut() {
+         cascade.set_period(period_sec);
+      }
+   }
+
+   pub fn set_choppiness(&mut self, choppiness: f32) {
+      self.choppiness = choppiness;
+      for cascade in self.cascades.iter_mut() {
+         cascade.set_choppiness(choppiness);
+      }
+   }
+
+   pub fn set_spectrum(&mut self, display: &Display, make_spectrum: SpectrumFactory) {
+      for cascade in self.cascades.iter_mut() {
+         cascade.set_spectrum(display, make_spectrum());
+      }
+   }
+
+   pub fn set_spreading(&mut self, display: &Display, make_spreading: SpreadingFactory) {
+      for cascade in self.cascades.iter_mut() {
+         cascade.set_spreading(display, make_spreading());
+      }
+   }
+
+   pub fn set_wind(&mut self, display: &Display, wind: Wind) {
+      self.wind = wind;
+      for cascade in self.cascades.iter_mut() {
+         cascade.set_wind(display, wind);
+      }
+   }
+
+   pub fn set_spectrum_amplitude(&mut self, display: &Display, amplitude: f32) {
+      self.spectrum_amplitude = amplitude;
+      for cascade in self.cascades.iter_mut() {
+         cascade.set_spectrum_amplitude(display, amplitude);
+      }
+   }
+
+   pub fn set_length_cutoff(&mut self, display: &Display, length_cutoff_meters: f32) {
+      self.length_cutoff_meters = length_cutoff_meters;
+      for cascade in self.cascades.iter_mut() {
+         cascade.set_length_cutoff(display, length_cutoff_meters);
+      }
+   }
+
+   // Infinite depth for deep water
+   pub fn set_water_depth(&mut self, display: &Display, water_depth: f32) {
+      self.water_depth = water_depth;
+      for cascade in self.cascades.iter_mut() {
+         cascade.set_water_depth(display, water_depth);
+      }
+   }
+
+   // How long changes of wind, amplitude or cutoff fade in
+   pub fn set_transition_duration(&mut self, duration_sec: f32) {
+      self.transition_duration_sec = duration_sec;
+      for cascade in self.cascades.iter_mut() {
+         cascade.set_transition_duration(duration_sec);
+      }
+   }
+
+   pub fn set_seed(&mut self, display: &Display, seed: u64) {
+      self.seed = seed;
+      for idx in 0..self.cascades.len() {
+         let cascade_seed = self.cascade_seed(idx);
+         self.cascades[idx].set_seed(display, cascade_seed);
+      }
+   }
+
+   // Cascades get different seeds, so that their waves are not correlated
+   fn cascade_seed(&self, idx: usize) -> u64 {
+      self.seed.wrapping_add(idx as u64)
+   }
+
+   pub fn cascades(&self) -> &[HeightField] {
+      &self.cascades
+   }
+
+   // Maps of all cascades, to be summed up when rendering
+   pub fn surface_maps(&self) -> Vec<SurfaceMaps<'_>> {
+      self.cascades.iter().map(|cascade| SurfaceMaps {
+         displacement: cascade.displacement_map().unwrap(),
+         normal: cascade.normal_map().unwrap(),
+         jacobian: cascade.jacobian_map().unwrap(),
+         physical_size: cascade.physical_size(),
+      }).collect()
+   }
+
+   // Bands don't overlap, so variances of cascades are summed up
+   pub fn significant_wave_height(&self) -> f32 {
+      4.0 * f32::sqrt(self.cascades.iter()
+         .map(HeightField::expected_height_variance)
+         .sum::<f32>())
+   }
+}
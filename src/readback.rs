@@ -0,0 +1,139 @@
+// AsyncReadback - copying of displacement maps from GPU to CPU memory,
+// without waiting for the GPU to finish its work
+// Reading a texture directly blocks until all GPU commands are executed,
+// instead the texture is copied into one of several pixel buffer objects
+// (in GPU memory), and the buffer is read a few frames later, when its
+// fence signals that the copy is done
+// With N buffers, up to N copies are in flight, so the CPU gets the grid
+// computed 1 to N frames ago (reported as latency in frames)
+
+use crate::height_field_cpu::DisplacementGrid;
+use glium::{GlObject, Texture2d};
+extern crate gl;
+
+// Displacement grid, that was computed for the given simulation time
+pub struct ReadbackFrame {
+   pub frame: u64,
+   pub time: f32,
+   pub grid: DisplacementGrid,
+}
+
+struct ReadbackSlot {
+   buffer: gl::types::GLuint,
+   // set while the copy is in flight
+   fence: Option<gl::types::GLsync>,
+   frame: u64,
+   time: f32,
+}
+
+pub struct AsyncReadback {
+   size: usize,
+   slots: Vec<ReadbackSlot>,
+   next_slot: usize,
+   // number of requested frames, including the skipped ones
+   frame: u64,
+   latest: Option<ReadbackFrame>,
+}
+
+impl AsyncReadback {
+   // Buffers for RGBA 32-bit float texture of size x size texels
+   pub fn new(size: usize, buffer_count: usize) -> Self {
+      assert!(buffer_count > 0, "At least one buffer is needed for readback");
+      let slots = (0..buffer_count).map(|_| unsafe {
+         let mut buffer = 0;
+         gl::CreateBuffers(1, &mut buffer);
+         gl::NamedBufferData(buffer, Self::buffer_bytes(size) as isize,
+            std::ptr::null(), gl::STREAM_READ);
+         ReadbackSlot { buffer, fence: None, frame: 0, time: 0.0 }
+      }).collect();
+      Self {
+         size,
+         slots,
+         next_slot: 0,
+         frame: 0,
+         latest: None,
+      }
+   }
+
+   fn buffer_bytes(size: usize) -> usize {
+      size * size * 4 * std::mem::size_of::<f32>()
+   }
+
+   // Called once per frame: collects finished copies, then starts copying
+   // the texture (computed for the given time) into the next free buffer
+   // If all buffers are still in flight, the frame is skipped
+   pub fn request(&mut self, texture: &Texture2d, time: f32) {
+      self.poll();
+      let frame = self.frame;
+      self.frame += 1;
+      let slot = &mut self.slots[self.next_slot];
+      if slot.fence.is_some() {
+         return;
+      }
+      unsafe {
+         gl::BindBuffer(gl::PIXEL_PACK_BUFFER, slot.buffer);
+         gl::GetTextureImage(texture.get_id(), 0, gl::RGBA, gl::FLOAT,
+            Self::buffer_bytes(self.size) as i32, std::ptr::null_mut());
+         gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+         slot.fence = Some(gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0));
+      }
+      slot.frame = frame;
+      slot.time = time;
+      self.next_slot = (self.next_slot + 1) % self.slots.len();
+   }
+
+   // Copies finished buffers into CPU memory (doesn't wait for unfinished ones)
+   fn poll(&mut self) {
+      for slot in self.slots.iter_mut() {
+         let fence = match slot.fence {
+            Some(fence) => fence,
+            None => continue,
+         };
+         unsafe {
+            let status = gl::ClientWaitSync(fence, 0, 0);
+            if status != gl::ALREADY_SIGNALED && status != gl::CONDITION_SATISFIED {
+               continue;
+            }
+            gl::DeleteSync(fence);
+            slot.fence = None;
+            if self.latest.as_ref().is_some_and(|latest| latest.frame > slot.frame) {
+               continue;
+            }
+            let bytes = Self::buffer_bytes(self.size);
+            let data = gl::MapNamedBufferRange(slot.buffer, 0, bytes as isize, gl::MAP_READ_BIT);
+            // e.g. the context is lost, the previous grid is kept
+            if data.is_null() {
+               log::error!("Failed to map readback buffer (GL error {})", gl::GetError());
+               continue;
+            }
+            let texels = std::slice::from_raw_parts(data as *const f32, bytes / std::mem::size_of::<f32>());
+            let grid = DisplacementGrid::from_rgba(self.size, texels);
+            gl::UnmapNamedBuffer(slot.buffer);
+            self.latest = Some(ReadbackFrame { frame: slot.frame, time: slot.time, grid });
+         }
+      }
+   }
+
+   // The most recent grid, that finished copying
+   pub fn latest(&self) -> Option<&ReadbackFrame> {
+      self.latest.as_ref()
+   }
+
+   // How many frames ago the most recent grid was requested
+   pub fn latency_frames(&self) -> Option<u64> {
+      self.latest.as_ref().map(|latest| self.frame - 1 - latest.frame)
+   }
+}
+
+impl Drop for AsyncReadback {
+   fn drop(&mut self) {
+      for slot in self.slots.iter() {
+         unsafe {
+            if let Some(fence) = slot.fence {
+               gl::DeleteSync(fence);
+            }
+            gl::DeleteBuffers(1, &slot.buffer);
+         }
+      }
+   }
+}
@@ -1,10 +1,12 @@
 use glium::backend::glutin;
-use glium::uniforms::{Sampler, SamplerBehavior};
+use glium::uniforms::Sampler;
 use glium::{Frame, uniform, PolygonMode};
 use crate::camera::Camera;
+use crate::consts;
 
 // All OpenGL rendering wrapped here
 // - water mesh
+// - floating bodies
 // - debugging textures
 
 // Common interface
@@ -14,7 +16,7 @@ pub trait Renderer {
 
 // Mesh - fill triangles with texture
 // Wireframe - draw only lines
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
 pub enum DrawMode {
    Mesh, Wireframe
 }
@@ -56,6 +58,63 @@ impl<'a> DrawParametersVariant<'a> {
    }
 }
 
+// ============
+// Water shading parameters (see "mesh_grid.frag")
+// ============
+#[derive(Copy, Clone)]
+pub struct WaterLighting {
+   pub sun_direction: glam::Vec3, // unit vector towards the sun
+   pub sun_color: [f32; 3],
+   pub sun_intensity: f32,
+   pub sky_zenith_color: [f32; 3],
+   pub sky_horizon_color: [f32; 3],
+   pub roughness: f32,
+   // color of light scattered by water itself, and color of the bottom
+   pub scatter_color: [f32; 3],
+   pub seabed_color: [f32; 3],
+   pub absorption: [f32; 3], // per meter, for every color channel
+   pub water_depth: f32, // meters
+   pub subsurface_scattering: f32,
+   // whitecaps appear where Jacobian of displacement drops below the threshold
+   // (1 - flat water, 0 - the surface folds over), zero intensity disables them
+   pub foam_color: [f32; 3],
+   pub foam_threshold: f32,
+   pub foam_intensity: f32,
+}
+
+impl WaterLighting {
+   // Azimuth is measured from X axis towards Z axis, elevation from the horizon
+   pub fn sun_direction_from_angles(azimuth_deg: f32, elevation_deg: f32) -> glam::Vec3 {
+      let (azimuth, elevation) = (azimuth_deg.to_radians(), elevation_deg.to_radians());
+      glam::vec3(
+         elevation.cos() * azimuth.cos(),
+         elevation.sin(),
+         elevation.cos() * azimuth.sin())
+   }
+}
+
+impl Default for WaterLighting {
+   fn default() -> Self {
+      Self {
+         sun_direction: Self::sun_direction_from_angles(
+            consts::SUN_AZIMUTH_DEGREES, consts::SUN_ELEVATION_DEGREES),
+         sun_color: [1.0, 0.95, 0.85],
+         sun_intensity: 5.0,
+         sky_zenith_color: [0.25, 0.45, 0.8],
+         sky_horizon_color: [0.75, 0.85, 0.95],
+         roughness: 0.2,
+         scatter_color: [0.0, 0.12, 0.15],
+         seabed_color: [0.35, 0.3, 0.2],
+         absorption: [0.45, 0.09, 0.06],
+         water_depth: 30.0,
+         subsurface_scattering: 0.5,
+         foam_color: [0.9, 0.92, 0.95],
+         foam_threshold: 0.3,
+         foam_intensity: 1.0,
+      }
+   }
+}
+
 // ============
 // Water mesh
 // ============
@@ -64,8 +123,26 @@ pub struct WaterRenderer<'a> {
    mesh_grid_vertices: glium::VertexBuffer<crate::mesh_grid::Vertex>,
    mesh_grid_indices: glium::index::NoIndices,
    draw_parameters: DrawParametersVariant<'a>,
-   albedo_map: glium::Texture2d,
    mesh_grid_model: glam::Affine3A,
+   lighting: WaterLighting,
+
+   // horizontal displacement multiplier, the larger - the sharper wave crests
+   choppiness: f32,
+
+   // zero displacement and upward normals, to draw the mesh flat
+   // when no height field is given
+   flat_displacement_map: glium::Texture2d,
+   flat_normal_map: glium::Texture2d,
+   flat_jacobian_map: glium::Texture2d,
+}
+
+// Maps of one height field (e.g. from HeightField), that cover a square
+// of physical_size meters, and repeat beyond it
+pub struct SurfaceMaps<'t> {
+   pub displacement: &'t glium::Texture2d, // X - displacement x, Y - height, Z - displacement z
+   pub normal: &'t glium::Texture2d,
+   pub jacobian: &'t glium::Texture2d,
+   pub physical_size: f32,
 }
 
 impl<'a> WaterRenderer<'a> {
@@ -74,19 +151,32 @@ impl<'a> WaterRenderer<'a> {
       let (mesh_grid_vertices, mesh_grid_indices)
          = crate::mesh_grid::make_tri_mesh(display, grid_size, facet_size);
       let draw_parameters = DrawParametersVariant::new();
-      let albedo_map = crate::mesh_grid::make_textures(display);
       let mesh_grid_model = glam::Affine3A::from_translation(
          Self::get_grid_center(grid_size, facet_size).into());
+      let flat_displacement_map = Self::make_constant_map(display, (0.0, 0.0, 0.0, 1.0));
+      let flat_normal_map = Self::make_constant_map(display, (0.0, 1.0, 0.0, 1.0));
+      let flat_jacobian_map = Self::make_constant_map(display, (1.0, 1.0, 1.0, 1.0));
       Self {
          mesh_grid_shader: mesh_grid_program,
          mesh_grid_vertices,
          mesh_grid_indices,
          draw_parameters,
-         albedo_map,
          mesh_grid_model,
+         lighting: WaterLighting::default(),
+         choppiness: consts::CHOPPINESS,
+         flat_displacement_map,
+         flat_normal_map,
+         flat_jacobian_map,
       }
    }
 
+   fn make_constant_map(display: &glutin::Display, value: (f32, f32, f32, f32)) -> glium::Texture2d {
+      glium::Texture2d::with_format(display,
+         vec![vec![value]],
+         glium::texture::UncompressedFloatFormat::F32F32F32F32,
+         glium::texture::MipmapsOption::NoMipmap).unwrap()
+   }
+
    pub fn recreate_mesh_grid(&mut self, display: &glutin::Display, grid_size: (u32, u32), facet_size: f32) {
       let (mesh_grid_vertices, mesh_grid_indices)
          = crate::mesh_grid::make_tri_mesh(display, grid_size, facet_size);
@@ -100,30 +190,175 @@ impl<'a> WaterRenderer<'a> {
           0.0, grid_size.1 as f32 * facet_size * -0.5)
    }
 
+   // The mesh is centered around the origin, while height fields start at it,
+   // so world coordinates are shifted before sampling a height field
+   pub fn world_to_grid(&self, world_xz: glam::Vec2) -> glam::Vec2 {
+      let translation = self.mesh_grid_model.translation;
+      world_xz - glam::vec2(translation.x, translation.z)
+   }
+
    pub fn set_draw_mode(&mut self, draw_mode: DrawMode) {
       self.draw_parameters.current_mode = draw_mode;
    }
-}
 
-impl<'a> Renderer for WaterRenderer<'a> {
-   fn draw_to(&self, frame: &mut Frame, camera: &Camera) {
+   pub fn set_choppiness(&mut self, choppiness: f32) {
+      self.choppiness = choppiness;
+   }
+
+   pub fn set_lighting(&mut self, lighting: WaterLighting) {
+      self.lighting = lighting;
+   }
+
+   // Vertices of the mesh are moved by the sum of displacement maps,
+   // and shaded with normals combined from the normal maps
+   // (e.g. all cascades of OceanCascades, up to consts::MAX_CASCADES)
+   pub fn draw_displaced_to(&self, frame: &mut Frame, camera: &Camera, cascades: &[SurfaceMaps]) {
       use glium::Surface;
-      let albedo_map = Sampler::new(&self.albedo_map);
-      albedo_map.anisotropy(8);
-      albedo_map.minify_filter(glium::uniforms::MinifySamplerFilter::LinearMipmapNearest);
-      albedo_map.magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear);
-      let uniforms = &uniform! {
+      assert!(cascades.len() <= consts::MAX_CASCADES,
+         "At most {} cascades can be rendered", consts::MAX_CASCADES);
+      // missing cascades are flat, so they don't change the sum
+      let flat_maps = SurfaceMaps {
+         displacement: &self.flat_displacement_map,
+         normal: &self.flat_normal_map,
+         jacobian: &self.flat_jacobian_map,
+         physical_size: 1.0,
+      };
+      let maps = (0..consts::MAX_CASCADES)
+         .map(|idx| cascades.get(idx).unwrap_or(&flat_maps))
+         .collect::<Vec<_>>();
+      let lighting = &self.lighting;
+      let camera_position: glam::Vec3 = camera.view().inverse().translation.into();
+      let uniforms = uniform! {
+         model: glam::Mat4::from(self.mesh_grid_model).to_cols_array_2d(),
          model_view_projection: (*camera.view_projection() * self.mesh_grid_model).to_cols_array_2d(),
-         albedo_map: albedo_map,
+         displacement_map0: Self::periodic_sampler(maps[0].displacement),
+         displacement_map1: Self::periodic_sampler(maps[1].displacement),
+         displacement_map2: Self::periodic_sampler(maps[2].displacement),
+         displacement_map3: Self::periodic_sampler(maps[3].displacement),
+         normal_map0: Self::periodic_sampler(maps[0].normal),
+         normal_map1: Self::periodic_sampler(maps[1].normal),
+         normal_map2: Self::periodic_sampler(maps[2].normal),
+         normal_map3: Self::periodic_sampler(maps[3].normal),
+         jacobian_map0: Self::periodic_sampler(maps[0].jacobian),
+         jacobian_map1: Self::periodic_sampler(maps[1].jacobian),
+         jacobian_map2: Self::periodic_sampler(maps[2].jacobian),
+         jacobian_map3: Self::periodic_sampler(maps[3].jacobian),
+         physical_sizes: [maps[0].physical_size, maps[1].physical_size,
+            maps[2].physical_size, maps[3].physical_size],
+         choppiness: self.choppiness,
+         camera_position: camera_position.to_array(),
+         sun_direction: lighting.sun_direction.to_array(),
+         sun_color: lighting.sun_color,
+         sun_intensity: lighting.sun_intensity,
+         sky_zenith_color: lighting.sky_zenith_color,
+         sky_horizon_color: lighting.sky_horizon_color,
+         roughness: lighting.roughness,
+         scatter_color: lighting.scatter_color,
+         seabed_color: lighting.seabed_color,
+         absorption: lighting.absorption,
+         water_depth: lighting.water_depth,
+         subsurface_scattering: lighting.subsurface_scattering,
+         foam_color: lighting.foam_color,
+         foam_threshold: lighting.foam_threshold,
+         foam_intensity: lighting.foam_intensity,
       };
       frame.draw(
          &self.mesh_grid_vertices,
-         &self.mesh_grid_indices,
+         self.mesh_grid_indices,
          &self.mesh_grid_shader,
-         uniforms,
+         &uniforms,
          self.draw_parameters.current_parameters(),
       ).unwrap()
    }
+
+   // Height field maps are periodic, and sampled between texels
+   fn periodic_sampler(texture: &glium::Texture2d) -> Sampler<'_, glium::Texture2d> {
+      Sampler::new(texture)
+         .wrap_function(glium::uniforms::SamplerWrapFunction::Repeat)
+         .minify_filter(glium::uniforms::MinifySamplerFilter::Linear)
+         .magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear)
+   }
+}
+
+impl<'a> Renderer for WaterRenderer<'a> {
+   fn draw_to(&self, frame: &mut Frame, camera: &Camera) {
+      self.draw_displaced_to(frame, camera, &[]);
+   }
+}
+
+// ============
+// Floating bodies
+// ============
+#[derive(Copy, Clone)]
+pub struct BodyVertex {
+   pub position: [f32; 3],
+   pub normal: [f32; 3],
+}
+
+glium::implement_vertex!(BodyVertex, position, normal);
+
+pub struct BodyRenderer<'a> {
+   program: glium::Program,
+   vertices: glium::VertexBuffer<BodyVertex>,
+   draw_parameters: glium::DrawParameters<'a>,
+   model: glam::Affine3A,
+   color: [f32; 3],
+   lighting: WaterLighting,
+}
+
+impl<'a> BodyRenderer<'a> {
+   // Triangles of the hull (e.g. buoyancy::Hull) get their own vertices,
+   // so that every triangle is flat shaded
+   pub fn new(display: &glutin::Display, hull: &crate::buoyancy::Hull, color: [f32; 3]) -> Self {
+      let vertices = hull.triangles().flat_map(|[a, b, c]| {
+         let normal = (b - a).cross(c - a).normalize_or_zero().to_array();
+         [a, b, c].map(|position| BodyVertex { position: position.to_array(), normal })
+      }).collect::<Vec<_>>();
+      Self {
+         program: crate::shader::make_program!(display, "shaders/body.vert", "shaders/body.frag"),
+         vertices: glium::VertexBuffer::new(display, &vertices).unwrap(),
+         draw_parameters: glium::DrawParameters {
+            depth: glium::Depth {
+               test: glium::draw_parameters::DepthTest::IfLess,
+               write: true,
+               .. Default::default()
+            },
+            .. Default::default()
+         },
+         model: glam::Affine3A::IDENTITY,
+         color,
+         lighting: WaterLighting::default(),
+      }
+   }
+
+   // Local to world coordinates, e.g. RigidBody::transform()
+   pub fn set_model(&mut self, model: glam::Affine3A) {
+      self.model = model;
+   }
+
+   pub fn set_lighting(&mut self, lighting: WaterLighting) {
+      self.lighting = lighting;
+   }
+}
+
+impl<'a> Renderer for BodyRenderer<'a> {
+   fn draw_to(&self, frame: &mut Frame, camera: &Camera) {
+      use glium::Surface;
+      let uniforms = uniform! {
+         model: glam::Mat4::from(self.model).to_cols_array_2d(),
+         model_view_projection: (*camera.view_projection() * self.model).to_cols_array_2d(),
+         color: self.color,
+         sun_direction: self.lighting.sun_direction.to_array(),
+         sun_color: self.lighting.sun_color,
+      };
+      frame.draw(
+         &self.vertices,
+         glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+         &self.program,
+         &uniforms,
+         &self.draw_parameters,
+      ).unwrap()
+   }
 }
 
 // ============
@@ -150,7 +385,7 @@ impl<'a> TextureBlitter<'a> {
 }
 
 impl<'a> Renderer for TextureBlitter<'a> {
-   fn draw_to(&self, frame: &mut Frame, camera: &Camera) {
+   fn draw_to(&self, frame: &mut Frame, _camera: &Camera) {
       if let Some(texture) = self.texture {
         use glium::Surface;
         texture.as_surface().blit_whole_color_to(frame, &self.blit_rectangle,
@@ -0,0 +1,21 @@
+// Fragment shader program for floating rigid bodies
+
+// Simple diffuse lighting by the sun, plus constant ambient light
+
+#version 330
+
+precision highp float;
+
+uniform vec3 color;
+uniform vec3 sun_direction; // towards the sun
+uniform vec3 sun_color;
+
+in vec3 v_world_normal;
+
+out vec4 o_color;
+
+void main() {
+   vec3 normal = normalize(v_world_normal);
+   float diffuse = max(dot(normal, normalize(sun_direction)), 0.0);
+   o_color = vec4(color * (0.25 + sun_color * diffuse), 1.0);
+}
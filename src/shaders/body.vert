@@ -0,0 +1,21 @@
+// Vertex shader program for floating rigid bodies
+
+// Takes 3D position of a hull vertex in local coordinates of the body,
+// and the normal of its triangle (triangles are flat shaded),
+// the model matrix places the body in the world coordinate space
+
+#version 330
+
+uniform mat4 model;
+uniform mat4 model_view_projection;
+
+in vec3 position;
+in vec3 normal;
+
+out vec3 v_world_normal;
+
+void main() {
+   // the model matrix has only rotation and translation
+   v_world_normal = mat3(model) * normal;
+   gl_Position = model_view_projection * vec4(position, 1.0);
+}
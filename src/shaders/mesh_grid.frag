@@ -1,21 +1,131 @@
 // Fragment shader program runs for every pixel on a rendered image
 
-// This particular shader takes texture coordinates (u, v) that are barycantrically
+// This particular shader gets world position of the water surface and
+// its position before displacement, that are barycantrically
 // interpolated between 3 vertices of a triangle
 
-// Using those texture coordinates we get color value for this pixel
-// from a water texture (see ../textures folder)
+// Surface normal is combined from normal maps of 4 cascades (by summing up
+// slopes), then the color is combined from:
+// - reflection of the sky (gradient from horizon to zenith color),
+//   weighted by Fresnel term (Schlick approximation, water F0 = 0.02)
+// - sun specular highlight (GGX microfacet distribution)
+// - light coming from under the surface, that is absorbed exponentially
+//   with the distance traveled in water (so deep water gets darker)
+// - subsurface scattering approximation, i.e. sunlight passing through
+//   the wave crests, when looking towards the sun
+// - foam on breaking crests, where the Jacobian of displacement is small
+//   (deviations of cascades' Jacobians from 1 are summed up)
 
 #version 330
 
 precision highp float;
 
-uniform sampler2D albedo_map;
+const float PI = 3.14159265;
+const float WATER_F0 = 0.02;
 
-in vec2 v_tex_coord;
+uniform sampler2D normal_map0;
+uniform sampler2D normal_map1;
+uniform sampler2D normal_map2;
+uniform sampler2D normal_map3;
+uniform sampler2D jacobian_map0;
+uniform sampler2D jacobian_map1;
+uniform sampler2D jacobian_map2;
+uniform sampler2D jacobian_map3;
+uniform vec4 physical_sizes;
+uniform vec3 camera_position;
+
+uniform vec3 sun_direction; // towards the sun
+uniform vec3 sun_color;
+uniform float sun_intensity;
+uniform vec3 sky_zenith_color;
+uniform vec3 sky_horizon_color;
+uniform float roughness;
+uniform vec3 scatter_color;
+uniform vec3 seabed_color;
+uniform vec3 absorption; // per meter, for every color channel
+uniform float water_depth; // meters
+uniform float subsurface_scattering;
+uniform vec3 foam_color;
+uniform float foam_threshold;
+uniform float foam_intensity;
+
+in vec3 v_world_position;
+in vec2 v_grid_position;
 
 out vec4 o_color;
 
+vec3 sky_color(vec3 direction) {
+   float elevation = clamp(direction.y, 0.0, 1.0);
+   return mix(sky_horizon_color, sky_zenith_color, sqrt(elevation));
+}
+
+float fresnel_schlick(float cos_theta) {
+   return WATER_F0 + (1.0 - WATER_F0) * pow(1.0 - clamp(cos_theta, 0.0, 1.0), 5.0);
+}
+
+float ggx_distribution(float n_dot_h, float alpha) {
+   float alpha2 = alpha * alpha;
+   float denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+   return alpha2 / (PI * denominator * denominator);
+}
+
+// slope of the surface (dh/dx, dh/dz), the same way as in the vertex shader
+vec2 sample_slope(sampler2D normal_map, float physical_size) {
+   vec2 uv = v_grid_position / physical_size + 0.5 / vec2(textureSize(normal_map, 0));
+   vec3 normal = texture(normal_map, uv).xyz;
+   return -normal.xz / max(normal.y, 1e-3);
+}
+
+float sample_jacobian(sampler2D jacobian_map, float physical_size) {
+   vec2 uv = v_grid_position / physical_size + 0.5 / vec2(textureSize(jacobian_map, 0));
+   return texture(jacobian_map, uv).x;
+}
+
 void main() {
-   o_color = texture(albedo_map, v_tex_coord);
-}
\ No newline at end of file
+   vec2 slope = sample_slope(normal_map0, physical_sizes.x)
+      + sample_slope(normal_map1, physical_sizes.y)
+      + sample_slope(normal_map2, physical_sizes.z)
+      + sample_slope(normal_map3, physical_sizes.w);
+   vec3 normal = normalize(vec3(-slope.x, 1.0, -slope.y));
+   vec3 view = normalize(camera_position - v_world_position);
+   vec3 sun = normalize(sun_direction);
+   // at grazing angles interpolated normal may face away from the camera
+   float n_dot_v = max(dot(normal, view), 1e-3);
+
+   float fresnel = fresnel_schlick(n_dot_v);
+   vec3 reflected = sky_color(reflect(-view, normal));
+
+   vec3 halfway = normalize(view + sun);
+   float n_dot_h = max(dot(normal, halfway), 0.0);
+   float alpha = max(roughness * roughness, 1e-3);
+   // Cook-Torrance with simplified visibility term 1/(4 n.l n.v)
+   vec3 specular = sun_color * sun_intensity * ggx_distribution(n_dot_h, alpha)
+      * fresnel_schlick(dot(halfway, view)) / (4.0 * n_dot_v);
+   specular *= step(0.0, dot(normal, sun));
+
+   // light path through the water: down to the seabed along the view ray
+   float depth_below_surface = max(water_depth + v_world_position.y, 0.0);
+   float path_length = depth_below_surface / max(view.y, 0.1);
+   vec3 transmittance = exp(-absorption * path_length);
+   vec3 ambient = sky_color(vec3(0.0, 1.0, 0.0));
+   vec3 refracted = ambient * mix(scatter_color, seabed_color, transmittance);
+
+   // sunlight passing through higher parts of the waves
+   float towards_sun = pow(max(dot(view, -sun), 0.0), 4.0);
+   float crest = max(v_world_position.y, 0.0);
+   vec3 subsurface = scatter_color * sun_color * sun_intensity
+      * subsurface_scattering * towards_sun * crest * (0.5 - 0.5 * dot(normal, sun));
+
+   vec3 color = mix(refracted + subsurface, reflected, fresnel) + specular;
+
+   float jacobian = 1.0
+      + (sample_jacobian(jacobian_map0, physical_sizes.x) - 1.0)
+      + (sample_jacobian(jacobian_map1, physical_sizes.y) - 1.0)
+      + (sample_jacobian(jacobian_map2, physical_sizes.z) - 1.0)
+      + (sample_jacobian(jacobian_map3, physical_sizes.w) - 1.0);
+   // foam is fully opaque half a unit of Jacobian below the threshold
+   float foam = foam_intensity * clamp((foam_threshold - jacobian) * 2.0, 0.0, 1.0);
+   vec3 foam_light = ambient + sun_color * sun_intensity * 0.2 * max(dot(normal, sun), 0.0);
+   color = mix(color, foam_color * foam_light, clamp(foam, 0.0, 1.0));
+   o_color = vec4(color, 1.0);
+}
@@ -1,7 +1,7 @@
 // Vertex shader program runs for vertex in a mesh
 
 // This particular shader takes 2D position (x,y) on a flat plane
-// and texture coordinate of the current vertex
+// of the current vertex
 // (those values are passed from calling CPU code)
 
 // The shader then defines a 3D position of the vertex in the coordinates space
@@ -10,16 +10,43 @@
 // view matrix - 4x4 matrix that transforms world coordinates to camera coordinate space
 // projection matrix - 4x4 matrix, that maps homogeneous 4D points to image coordinates
 
+// Before that, the vertex is moved by the sum of 4 displacement maps (cascades),
+// every one covers a square of physical_sizes[i] meters and repeats periodically
+// Horizontal displacement is subtracted, which moves vertices towards wave crests
+
 #version 330
 
+uniform mat4 model;
 uniform mat4 model_view_projection;
+uniform sampler2D displacement_map0;
+uniform sampler2D displacement_map1;
+uniform sampler2D displacement_map2;
+uniform sampler2D displacement_map3;
+uniform vec4 physical_sizes;
+uniform float choppiness;
 
 in vec2 position;
-in vec2 tex_coord;
 
-out vec2 v_tex_coord;
+out vec3 v_world_position;
+out vec2 v_grid_position;
+
+// vertex i lies at the center of texel i
+vec3 sample_displacement(sampler2D displacement_map, float physical_size) {
+   vec2 uv = position / physical_size + 0.5 / vec2(textureSize(displacement_map, 0));
+   return texture(displacement_map, uv).xyz;
+}
 
 void main() {
-   gl_Position = model_view_projection * vec4(position.x, 0.0, position.y, 1.0);
-   v_tex_coord = tex_coord;
+   vec3 displacement = sample_displacement(displacement_map0, physical_sizes.x)
+      + sample_displacement(displacement_map1, physical_sizes.y)
+      + sample_displacement(displacement_map2, physical_sizes.z)
+      + sample_displacement(displacement_map3, physical_sizes.w);
+
+   vec3 displaced_position = vec3(
+      position.x - choppiness * displacement.x,
+      displacement.y,
+      position.y - choppiness * displacement.z);
+   gl_Position = model_view_projection * vec4(displaced_position, 1.0);
+   v_world_position = (model * vec4(displaced_position, 1.0)).xyz;
+   v_grid_position = position;
 }
\ No newline at end of file
@@ -0,0 +1,240 @@
+// Sea state statistics, the way oceanographers describe waves:
+// - significant wave height Hs = 4 * standard deviation of elevation
+// - maximum wave height Hmax (from crest to trough, between zero up-crossings)
+// - mean zero-crossing period Tz = 2pi * sqrt(m0/m2), where mN are moments
+//   of the energy spectrum w.r.t. angular frequency
+// - peak period Tp, of waves with the most energy
+// - 1D energy spectrum w.r.t. wavevector magnitude (energy of 2D spectrum
+//   summed over rings of wavevectors, i.e. radially averaged)
+// Simulated statistics are found from a height field snapshot, while expected
+// ones come from the spectrum model, that the height field is generated from
+// Periods are derived from wavenumbers by the dispersion relation
+// (of deep water, or of water of the given depth)
+
+use crate::consts;
+use crate::height_field::{HeightField, SpectrumParameters};
+use crate::height_field_cpu::DisplacementGrid;
+
+pub struct SeaStateStatistics {
+   pub significant_wave_height: f32,
+   pub max_wave_height: f32,
+   pub mean_zero_crossing_period: f32,
+   pub peak_period: f32,
+   // number of waves that Hmax was chosen from
+   pub wave_count: usize,
+   // energy density (m^2 per rad/m) at wavenumbers 0, step, 2*step, ...
+   pub spectrum: Vec<f32>,
+   pub wavenumber_step: f32,
+}
+
+impl SeaStateStatistics {
+   pub fn from_grid(grid: &DisplacementGrid, physical_size: f32, water_depth: f32) -> Self {
+      let size = grid.size;
+      let mean = grid.dy.iter().sum::<f32>() / grid.dy.len() as f32;
+      let heights = grid.dy.iter().map(|&height| height - mean).collect::<Vec<_>>();
+
+      // waves along rows and columns, the grid is periodic, so every line
+      // is started from its first up-crossing and wrapped around
+      let (wave_count, max_wave_height) = (0..size)
+         .flat_map(|line| [
+            (0..size).map(|col| heights[line*size + col]).collect::<Vec<_>>(),
+            (0..size).map(|row| heights[row*size + line]).collect::<Vec<_>>(),
+         ])
+         .map(|line| Self::zero_crossing_waves(&line))
+         .fold((0, 0.0), |(count, max_height), (line_count, line_max)|
+            (count + line_count, f32::max(max_height, line_max)));
+
+      let amplitudes = forward_fft_2d(&heights, size);
+      let wavenumber_step = 2.0 * consts::PI / physical_size;
+      let energies = (0..size*size).map(|idx| {
+         let (row, col) = (idx / size, idx % size);
+         let signed = |index: usize| if index < size / 2 { index as f32 } else { index as f32 - size as f32 };
+         let wavevector = wavenumber_step * glam::vec2(signed(col), signed(row));
+         (wavevector.length(), amplitudes[idx].length_squared())
+      });
+      let variance = heights.iter().map(|height| height * height).sum::<f32>() / heights.len() as f32;
+      let mut statistics = Self::from_energies(energies, variance, size, wavenumber_step, water_depth);
+      statistics.wave_count = wave_count;
+      statistics.max_wave_height = max_wave_height;
+      statistics
+   }
+
+   // From the same spectrum, that HeightField generates waves from
+   // Hmax is the most probable height of the largest one of wave_count waves
+   // (heights have Rayleigh distribution)
+   pub fn expected(size: usize, physical_size: f32, parameters: &SpectrumParameters, wave_count: usize) -> Self {
+      let energies = (0..size*size).map(|idx| {
+         let coords = (idx / size, idx % size);
+         let wavevector = crate::wave::wavevector_from_coords(coords, size, physical_size);
+         (wavevector.length(), HeightField::wave_energy(coords, size, physical_size, parameters))
+      });
+      let variance = HeightField::height_variance(size, physical_size, parameters);
+      let mut statistics = Self::from_energies(energies, variance, size,
+         2.0 * consts::PI / physical_size, parameters.water_depth);
+      statistics.set_expected_wave_count(wave_count);
+      statistics.peak_period = 2.0 * consts::PI
+         / parameters.spectrum.peak_frequency(parameters.wind);
+      statistics
+   }
+
+   fn set_expected_wave_count(&mut self, wave_count: usize) {
+      self.wave_count = wave_count;
+      self.max_wave_height = self.significant_wave_height
+         * f32::sqrt(0.5 * f32::ln(usize::max(wave_count, 1) as f32));
+   }
+
+   // Pairs of wavevector magnitude and energy of the wave
+   // Only wavevectors within the largest ring (up to size/2 steps) go into
+   // the 1D spectrum and periods, while Hs comes from the whole variance
+   // of elevation (corners of the lattice included)
+   fn from_energies(energies: impl Iterator<Item = (f32, f32)>, height_variance: f32, size: usize,
+      wavenumber_step: f32, water_depth: f32) -> Self {
+      let mut spectrum = vec![0.0; size / 2 + 1];
+      let (mut moment0, mut moment2) = (0.0, 0.0);
+      for (wavenumber, energy) in energies {
+         let bin = (wavenumber / wavenumber_step).round() as usize;
+         if bin == 0 || bin >= spectrum.len() {
+            continue;
+         }
+         spectrum[bin] += energy / wavenumber_step;
+         let frequency = crate::wave::dispersion_frequency_at_depth(wavenumber, water_depth);
+         moment0 += energy;
+         moment2 += frequency * frequency * energy;
+      }
+      let peak_bin = (1..spectrum.len())
+         .max_by(|&a, &b| spectrum[a].total_cmp(&spectrum[b]))
+         .unwrap_or(1);
+      let peak_frequency = crate::wave::dispersion_frequency_at_depth(
+         peak_bin as f32 * wavenumber_step, water_depth);
+      Self {
+         significant_wave_height: 4.0 * f32::sqrt(height_variance),
+         max_wave_height: 0.0,
+         mean_zero_crossing_period: 2.0 * consts::PI * f32::sqrt(moment0 / f32::max(moment2, f32::EPSILON)),
+         peak_period: 2.0 * consts::PI / peak_frequency,
+         wave_count: 0,
+         spectrum,
+         wavenumber_step,
+      }
+   }
+
+   // Number of waves between zero up-crossings of a periodic line
+   // of elevations, and the largest height among them
+   fn zero_crossing_waves(line: &[f32]) -> (usize, f32) {
+      let n = line.len();
+      let up_crossings = (0..n)
+         .filter(|&idx| line[idx] < 0.0 && line[(idx + 1) % n] >= 0.0)
+         .collect::<Vec<_>>();
+      let max_height = (0..up_crossings.len()).map(|wave| {
+         let start = up_crossings[wave];
+         let mut end = up_crossings[(wave + 1) % up_crossings.len()];
+         if end <= start {
+            end += n;
+         }
+         let (min, max) = (start..end).map(|idx| line[idx % n])
+            .fold((f32::MAX, f32::MIN), |(min, max), height| (min.min(height), max.max(height)));
+         max - min
+      }).fold(0.0, f32::max);
+      (up_crossings.len(), max_height)
+   }
+}
+
+// Statistics of a displacement grid computed by the height field,
+// and the expected ones for its spectrum
+pub struct SeaStateComparison {
+   pub simulated: SeaStateStatistics,
+   pub expected: SeaStateStatistics,
+   // see HeightField::spectrum_id
+   spectrum_id: u64,
+}
+
+impl SeaStateComparison {
+   pub fn new(height_field: &HeightField, grid: &DisplacementGrid) -> Self {
+      let simulated = SeaStateStatistics::from_grid(grid, height_field.physical_size(),
+         height_field.spectrum_parameters().water_depth);
+      let expected = SeaStateStatistics::expected(height_field.size(), height_field.physical_size(),
+         &height_field.spectrum_parameters(), simulated.wave_count);
+      Self { simulated, expected, spectrum_id: height_field.spectrum_id() }
+   }
+
+   // Expected statistics go through the whole spectrum model, so they're
+   // recomputed only when the spectrum changes (or it's another height field)
+   pub fn update(&mut self, height_field: &HeightField, grid: &DisplacementGrid) {
+      if height_field.spectrum_id() != self.spectrum_id {
+         *self = Self::new(height_field, grid);
+         return;
+      }
+      self.simulated = SeaStateStatistics::from_grid(grid, height_field.physical_size(),
+         height_field.spectrum_parameters().water_depth);
+      self.expected.set_expected_wave_count(self.simulated.wave_count);
+   }
+}
+
+// Amplitudes of complex exponents, that sum up to the given real values
+// (normalized, so that their squares sum up to the mean square of values)
+fn forward_fft_2d(values: &[f32], size: usize) -> Vec<glam::Vec2> {
+   let mut data = values.iter().map(|&value| glam::vec2(value, 0.0)).collect::<Vec<_>>();
+   let mut line = vec![glam::Vec2::ZERO; size];
+   for row in 0..size {
+      line.copy_from_slice(&data[row*size..(row + 1)*size]);
+      forward_fft(&mut line);
+      data[row*size..(row + 1)*size].copy_from_slice(&line);
+   }
+   for col in 0..size {
+      (0..size).for_each(|row| line[row] = data[row*size + col]);
+      forward_fft(&mut line);
+      (0..size).for_each(|row| data[row*size + col] = line[row]);
+   }
+   let normalization = 1.0 / (size * size) as f32;
+   data.iter().map(|&amplitude| amplitude * normalization).collect()
+}
+
+// In-place radix-2 Cooley-Tukey, length has to be a power of 2
+fn forward_fft(values: &mut [glam::Vec2]) {
+   let n = values.len();
+   let n_bits = n.trailing_zeros();
+   for idx in 0..n {
+      let reversed = idx.reverse_bits() >> (usize::BITS - n_bits);
+      if idx < reversed {
+         values.swap(idx, reversed);
+      }
+   }
+   let mut length = 2;
+   while length <= n {
+      let angle = -2.0 * consts::PI / length as f32;
+      for start in (0..n).step_by(length) {
+         for k in 0..length / 2 {
+            let twiddle = crate::complex::complex_exp(glam::vec2(0.0, angle * k as f32));
+            let (even, odd) = (values[start + k], values[start + k + length / 2]);
+            let odd = crate::complex::complex_mul(twiddle, odd);
+            values[start + k] = even + odd;
+            values[start + k + length / 2] = even - odd;
+         }
+      }
+      length *= 2;
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // Wave along X axis: height 2A, variance A^2/2, one wavenumber
+   #[test]
+   fn statistics_of_sine_wave() {
+      let (size, physical_size, amplitude, wave_count) = (64, 128.0, 1.5, 4);
+      let mut grid = DisplacementGrid::zeros(size);
+      for idx in 0..size*size {
+         let x = (idx % size) as f32 / size as f32;
+         grid.dy[idx] = amplitude * f32::sin(2.0 * consts::PI * wave_count as f32 * x + 0.3);
+      }
+      let statistics = SeaStateStatistics::from_grid(&grid, physical_size, f32::INFINITY);
+
+      let wavenumber = 2.0 * consts::PI * wave_count as f32 / physical_size;
+      let period = 2.0 * consts::PI / crate::wave::dispersion_frequency(wavenumber);
+      assert!((statistics.significant_wave_height - 4.0 * amplitude / f32::sqrt(2.0)).abs() < 1e-3);
+      assert!((statistics.max_wave_height - 2.0 * amplitude).abs() < 0.05);
+      assert!((statistics.peak_period - period).abs() < 1e-3);
+      assert!((statistics.mean_zero_crossing_period - period).abs() < 1e-3);
+      assert_eq!(statistics.wave_count, size * wave_count);
+   }
+}
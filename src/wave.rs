@@ -2,6 +2,7 @@ use crate::consts::{PI, G};
 
 // Convenience methods for some physical oceanographic relations
 
+#[derive(Copy, Clone)]
 pub struct Wind {
    velocity: f32,
    direction: glam::Vec2,
@@ -23,10 +24,12 @@ impl Wind {
    pub fn velocity(&self) -> f32 { self.velocity }
 }
 
-// To perform FFT, [0..N] range of lattice coordinates has to be remapped
+// To perform FFT, [0..N) range of lattice coordinates has to be remapped to [-N/2..N/2)
+// Columns go along X axis of the wavevector, rows go along Y axis (same as in textures)
 pub fn wavevector_from_coords((row, col): (usize, usize), lattice_size: usize, physical_size: f32) -> glam::Vec2 {
+   let half_size = (lattice_size / 2) as f32;
    2.0 * PI / physical_size *
-      glam::vec2(row as f32, col as f32)
+      glam::vec2(col as f32 - half_size, row as f32 - half_size)
 }
 
 // From oceanographic reserach it's known that in simplest case,
@@ -41,9 +44,303 @@ pub fn dispersion_frequency_shallow(wavevector_magnitude: f32, water_depth: f32)
       * f32::tanh(wavevector_magnitude*water_depth))
 }
 
+// Water depth in meters, infinite depth means deep water
+// Once depth exceeds half of the wavelength, waves don't feel the bottom
+// and both relations give the same frequency
+pub fn dispersion_frequency_at_depth(wavevector_magnitude: f32, water_depth: f32) -> f32 {
+   if water_depth.is_finite() {
+      dispersion_frequency_shallow(wavevector_magnitude, water_depth)
+   } else {
+      dispersion_frequency(wavevector_magnitude)
+   }
+}
+
+// Derivative of dispersion frequency w.r.t. wavevector magnitude (group velocity)
+pub fn dispersion_frequency_derivative(wavevector_magnitude: f32, water_depth: f32) -> f32 {
+   let frequency = dispersion_frequency_at_depth(wavevector_magnitude, water_depth);
+   if !water_depth.is_finite() {
+      return G / (2.0 * frequency);
+   }
+   let kh = wavevector_magnitude * water_depth;
+   let tanh_kh = f32::tanh(kh);
+   G * (tanh_kh + kh * (1.0 - tanh_kh * tanh_kh)) / (2.0 * frequency)
+}
+
 // Since default dispersion frequency is continuous, it's hard to
 // combine waves in such a way, so that the ocean movement has a certain period
 // It can be fixed by ensuring all frequencies are multples of some base frequency
 pub fn discrete_dispersion_frequency(dispersion_freq: f32, base_frequency: f32) -> f32 {
    (dispersion_freq / base_frequency).trunc() * base_frequency
-}
\ No newline at end of file
+}
+
+// Empirical spectra of oceanic waves
+// Each one defines omnidirectional energy density S(w) of waves
+// w.r.t. angular frequency w, for the given wind
+pub trait WaveSpectrum {
+   fn frequency_spectrum(&self, frequency: f32, wind: &Wind) -> f32;
+
+   // Angular frequency, at which the spectrum has its maximum
+   fn peak_frequency(&self, wind: &Wind) -> f32;
+}
+
+// Energy density of waves w.r.t. wavevector (2D spectrum)
+// Found from omnidirectional spectrum by change of variables w -> k,
+// and multiplying by directional spreading
+// (dispersion relation depends on water depth, see dispersion_frequency_at_depth)
+pub fn directional_spectrum(spectrum: &dyn WaveSpectrum, spreading: &dyn DirectionalSpreading, wave_vector: glam::Vec2, wind: &Wind, water_depth: f32) -> f32 {
+   let k_len = wave_vector.length();
+   if k_len < 0.0001 {
+      return 0.0;
+   }
+   let frequency = dispersion_frequency_at_depth(k_len, water_depth);
+   let frequency_derivative = dispersion_frequency_derivative(k_len, water_depth); // dw/dk
+
+   let wave_direction = wave_vector / k_len;
+   let angle = f32::atan2(
+      wind.direction().perp_dot(wave_direction),
+      wind.direction().dot(wave_direction));
+   let directional = spreading.spreading(
+      angle, frequency, spectrum.peak_frequency(wind), wind);
+
+   spectrum.frequency_spectrum(frequency, wind) * frequency_derivative / k_len * directional
+}
+
+// The most typical spectrum of oceanic waves, has many researched extensions
+// to improve convergence or impose requirements (like shallow water)
+// Originally defined w.r.t. wavevector magnitude as A*exp(-1/(kL)^2)/k^3
+// With A = alpha/2 (alpha = 0.0081 is Phillips constant), its tail matches Pierson-Moskowitz
+pub struct PhillipsSpectrum {
+   pub amplitude: f32,
+}
+
+impl WaveSpectrum for PhillipsSpectrum {
+   fn frequency_spectrum(&self, frequency: f32, wind: &Wind) -> f32 {
+      let k_len = frequency * frequency / G;
+      let k_3 = k_len * k_len * k_len;
+      let wavenumber_derivative = 2.0 * frequency / G; // dk/dw
+
+      let largest_wave_len = wind.largest_wavelength();
+      let numerator = f32::exp(-1.0 / (k_len * k_len * largest_wave_len * largest_wave_len));
+
+      self.amplitude * numerator / k_3 * wavenumber_derivative
+   }
+
+   fn peak_frequency(&self, wind: &Wind) -> f32 {
+      dispersion_frequency(f32::sqrt(0.5) / wind.largest_wavelength())
+   }
+}
+
+// Spectrum of fully developed sea, i.e. wind has been blowing
+// long enough over large enough area
+pub struct PiersonMoskowitzSpectrum;
+
+impl WaveSpectrum for PiersonMoskowitzSpectrum {
+   fn frequency_spectrum(&self, frequency: f32, wind: &Wind) -> f32 {
+      let alpha = 0.0081;
+      let peak_ratio = self.peak_frequency(wind) / frequency;
+      alpha * G * G / frequency.powi(5)
+         * f32::exp(-1.25 * peak_ratio.powi(4))
+   }
+
+   fn peak_frequency(&self, wind: &Wind) -> f32 {
+      0.87 * G / wind.velocity()
+   }
+}
+
+// Joint North Sea Wave Project spectrum, sea that is still developing,
+// limited by distance over which the wind blows (fetch, meters)
+// Energy near the peak is enhanced by factor gamma (3.3 on average)
+pub struct JonswapSpectrum {
+   pub peak_enhancement: f32,
+   pub fetch: f32,
+}
+
+impl WaveSpectrum for JonswapSpectrum {
+   fn frequency_spectrum(&self, frequency: f32, wind: &Wind) -> f32 {
+      let dimensionless_fetch = G * self.fetch / (wind.velocity() * wind.velocity());
+      let alpha = 0.076 * dimensionless_fetch.powf(-0.22);
+      let peak_frequency = self.peak_frequency(wind);
+      let sigma = if frequency <= peak_frequency { 0.07 } else { 0.09 };
+      let peak_distance = (frequency - peak_frequency) / (sigma * peak_frequency);
+      let r = f32::exp(-0.5 * peak_distance * peak_distance);
+      let peak_ratio = peak_frequency / frequency;
+
+      alpha * G * G / frequency.powi(5)
+         * f32::exp(-1.25 * peak_ratio.powi(4))
+         * self.peak_enhancement.powf(r)
+   }
+
+   fn peak_frequency(&self, wind: &Wind) -> f32 {
+      22.0 * f32::cbrt(G * G / (wind.velocity() * self.fetch))
+   }
+}
+
+// Texel-MARSEN-ARSLOE spectrum, JONSWAP spectrum attenuated in water of finite depth (meters)
+pub struct TmaSpectrum {
+   pub jonswap: JonswapSpectrum,
+   pub depth: f32,
+}
+
+impl TmaSpectrum {
+   // Kitaigorodskii depth attenuation
+   fn depth_attenuation(&self, frequency: f32) -> f32 {
+      let frequency_h = frequency * f32::sqrt(self.depth / G);
+      if frequency_h <= 1.0 {
+         0.5 * frequency_h * frequency_h
+      } else if frequency_h < 2.0 {
+         1.0 - 0.5 * (2.0 - frequency_h) * (2.0 - frequency_h)
+      } else {
+         1.0
+      }
+   }
+}
+
+impl WaveSpectrum for TmaSpectrum {
+   fn frequency_spectrum(&self, frequency: f32, wind: &Wind) -> f32 {
+      self.jonswap.frequency_spectrum(frequency, wind) * self.depth_attenuation(frequency)
+   }
+
+   fn peak_frequency(&self, wind: &Wind) -> f32 {
+      self.jonswap.peak_frequency(wind)
+   }
+}
+
+
+// Distribution of waves energy among directions, depending on the angle
+// between wave direction and wind direction [-pi..pi]
+// Has to integrate to 1 over all angles, so that it can multiply any omnidirectional spectrum
+pub trait DirectionalSpreading {
+   fn spreading(&self, angle: f32, frequency: f32, peak_frequency: f32, wind: &Wind) -> f32;
+}
+
+// Longuet-Higgins spreading Q(s)*|cos(angle/2)|^2s, where Q(s) normalizes the integral to 1
+// The bigger exponent s, the more waves are aligned with the wind (e.g. swell)
+fn cos_2s_spreading(angle: f32, exponent: f32) -> f32 {
+   let normalization = f32::exp(
+      (2.0 * exponent - 1.0) * std::f32::consts::LN_2 - f32::ln(PI)
+      + 2.0 * ln_gamma(exponent + 1.0) - ln_gamma(2.0 * exponent + 1.0));
+   normalization * f32::abs(f32::cos(0.5 * angle)).powf(2.0 * exponent)
+}
+
+// Extra exponent of cos-2s spreading, that turns wind waves into swell, mostly near the peak
+fn swell_exponent(frequency: f32, peak_frequency: f32, swell: f32) -> f32 {
+   16.0 * f32::tanh(peak_frequency / frequency) * swell * swell
+}
+
+// Logarithm of Gamma function, Lanczos approximation
+fn ln_gamma(x: f32) -> f32 {
+   const COEFFICIENTS: [f64; 9] = [
+      0.999_999_999_999_809_9, 676.520_368_121_885_1, -1_259.139_216_722_402_8,
+      771.323_428_777_653_1, -176.615_029_162_140_6, 12.507_343_278_686_905,
+      -0.138_571_095_265_720_12, 9.984_369_578_019_572e-6, 1.505_632_735_149_311_6e-7,
+   ];
+   let x = x as f64 - 1.0;
+   let t = x + 7.5;
+   let series = COEFFICIENTS.iter().skip(1).enumerate()
+      .fold(COEFFICIENTS[0], |sum, (i, c)| sum + c / (x + i as f64 + 1.0));
+   (0.5 * f64::ln(2.0 * std::f64::consts::PI) + (x + 0.5) * t.ln() - t + series.ln()) as f32
+}
+
+// cos-2s spreading with constant exponent for all frequencies
+pub struct Cos2sSpreading {
+   pub exponent: f32,
+}
+
+impl DirectionalSpreading for Cos2sSpreading {
+   fn spreading(&self, angle: f32, _frequency: f32, _peak_frequency: f32, _wind: &Wind) -> f32 {
+      cos_2s_spreading(angle, self.exponent)
+   }
+}
+
+// cos-2s spreading, with exponent measured by Mitsuyasu et al.,
+// waves are the most aligned with the wind at the peak frequency
+pub struct MitsuyasuSpreading {
+   pub swell: f32,
+}
+
+impl DirectionalSpreading for MitsuyasuSpreading {
+   fn spreading(&self, angle: f32, frequency: f32, peak_frequency: f32, wind: &Wind) -> f32 {
+      let peak_exponent = 11.5 * (peak_frequency * wind.velocity() / G).powf(-2.5);
+      let peak_ratio = frequency / peak_frequency;
+      let exponent = if peak_ratio <= 1.0 {
+         peak_exponent * peak_ratio.powi(5)
+      } else {
+         peak_exponent * peak_ratio.powf(-2.5)
+      };
+      cos_2s_spreading(angle,
+         exponent + swell_exponent(frequency, peak_frequency, self.swell))
+   }
+}
+
+// cos-2s spreading, with exponent measured by Hasselmann et al.
+pub struct HasselmannSpreading {
+   pub swell: f32,
+}
+
+impl DirectionalSpreading for HasselmannSpreading {
+   fn spreading(&self, angle: f32, frequency: f32, peak_frequency: f32, wind: &Wind) -> f32 {
+      let peak_ratio = frequency / peak_frequency;
+      let exponent = if peak_ratio <= 1.0 {
+         6.97 * peak_ratio.powf(4.06)
+      } else {
+         let wave_age = wind.velocity() * peak_frequency / G;
+         9.77 * peak_ratio.powf(-2.33 - 1.45 * (wave_age - 1.17))
+      };
+      cos_2s_spreading(angle,
+         exponent + swell_exponent(frequency, peak_frequency, self.swell))
+   }
+}
+
+// Spreading in form of sech^2(beta*angle), measured by Donelan and Banner,
+// has wider tails, than cos-2s
+pub struct DonelanBannerSpreading;
+
+impl DirectionalSpreading for DonelanBannerSpreading {
+   fn spreading(&self, angle: f32, frequency: f32, peak_frequency: f32, _wind: &Wind) -> f32 {
+      let peak_ratio = frequency / peak_frequency;
+      let beta = if peak_ratio < 0.95 {
+         2.61 * peak_ratio.powf(1.3)
+      } else if peak_ratio < 1.6 {
+         2.28 * peak_ratio.powf(-1.3)
+      } else {
+         let epsilon = -0.4 + 0.8393 * f32::exp(-0.567 * f32::ln(peak_ratio * peak_ratio));
+         f32::powf(10.0, epsilon)
+      };
+      let sech = 1.0 / f32::cosh(beta * angle);
+      beta / (2.0 * f32::tanh(beta * PI)) * sech * sech
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn dispersion_derivative_matches_finite_difference() {
+      let water_depth = 10.0;
+      for kh in [0.05, 0.3, 1.0, 3.0, 10.0] {
+         let wavenumber = kh / water_depth;
+         let step = 1e-3 * wavenumber;
+         let finite_difference = (dispersion_frequency_at_depth(wavenumber + step, water_depth)
+            - dispersion_frequency_at_depth(wavenumber - step, water_depth)) / (2.0 * step);
+         let derivative = dispersion_frequency_derivative(wavenumber, water_depth);
+         assert!((derivative - finite_difference).abs() < 1e-3 * derivative,
+            "kh {}: {} vs {}", kh, derivative, finite_difference);
+      }
+   }
+
+   // Waves much shorter than the depth don't feel the bottom
+   #[test]
+   fn deep_water_limit() {
+      for wavenumber in [0.1, 1.0, 10.0] {
+         let deep_depth = 20.0 / wavenumber;
+         let deep = dispersion_frequency(wavenumber);
+         assert!((dispersion_frequency_at_depth(wavenumber, deep_depth) - deep).abs() < 1e-5 * deep);
+         assert_eq!(dispersion_frequency_at_depth(wavenumber, f32::INFINITY), deep);
+         let deep_derivative = dispersion_frequency_derivative(wavenumber, f32::INFINITY);
+         assert!((deep_derivative - 0.5 * deep / wavenumber).abs() < 1e-5 * deep_derivative);
+         assert!((dispersion_frequency_derivative(wavenumber, deep_depth) - deep_derivative).abs()
+            < 1e-4 * deep_derivative);
+      }
+   }
+}
@@ -0,0 +1,121 @@
+// Scripted weather - wind speed and direction given at keyframes of time,
+// in between them the wind changes linearly (direction turns by the shorter arc)
+// Before the first keyframe and after the last one the wind is constant
+// The timeline only tells what the wind should be, changing of waves is
+// smoothed by HeightField (see HeightField::set_transition_duration)
+
+use crate::wave::Wind;
+
+#[derive(Copy, Clone, Debug)]
+pub struct WindKeyframe {
+   // seconds from the start of the timeline
+   pub time: f32,
+   pub speed: f32,
+   // angle from X axis towards Z axis
+   pub direction_deg: f32,
+}
+
+impl WindKeyframe {
+   pub fn wind(&self) -> Wind {
+      let direction = self.direction_deg.to_radians();
+      Wind::new(self.speed, glam::vec2(direction.cos(), direction.sin()))
+   }
+}
+
+#[derive(Default)]
+pub struct WindTimeline {
+   // sorted by time
+   keyframes: Vec<WindKeyframe>,
+}
+
+impl WindTimeline {
+   // A keyframe at the same time as an existing one replaces it
+   pub fn add(&mut self, keyframe: WindKeyframe) {
+      let idx = self.keyframes.partition_point(|existing| existing.time < keyframe.time);
+      match self.keyframes.get_mut(idx) {
+         Some(existing) if existing.time == keyframe.time => *existing = keyframe,
+         _ => self.keyframes.insert(idx, keyframe),
+      }
+   }
+
+   pub fn remove(&mut self, idx: usize) {
+      self.keyframes.remove(idx);
+   }
+
+   pub fn keyframes(&self) -> &[WindKeyframe] {
+      &self.keyframes
+   }
+
+   // Time of the last keyframe
+   pub fn duration(&self) -> f32 {
+      self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+   }
+
+   // Interpolated keyframe at the given time, None if there are no keyframes
+   pub fn at(&self, time: f32) -> Option<WindKeyframe> {
+      let first = *self.keyframes.first()?;
+      let last = *self.keyframes.last()?;
+      if time <= first.time {
+         return Some(WindKeyframe { time, ..first });
+      }
+      if time >= last.time {
+         return Some(WindKeyframe { time, ..last });
+      }
+      let next_idx = self.keyframes.partition_point(|keyframe| keyframe.time <= time);
+      let (from, to) = (self.keyframes[next_idx - 1], self.keyframes[next_idx]);
+      let t = (time - from.time) / (to.time - from.time);
+      let turn = (to.direction_deg - from.direction_deg + 180.0).rem_euclid(360.0) - 180.0;
+      Some(WindKeyframe {
+         time,
+         speed: from.speed + (to.speed - from.speed) * t,
+         direction_deg: (from.direction_deg + turn * t).rem_euclid(360.0),
+      })
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn keyframe(time: f32, speed: f32, direction_deg: f32) -> WindKeyframe {
+      WindKeyframe { time, speed, direction_deg }
+   }
+
+   #[test]
+   fn interpolation() {
+      let mut timeline = WindTimeline::default();
+      assert!(timeline.at(0.0).is_none());
+      timeline.add(keyframe(10.0, 10.0, 350.0));
+      timeline.add(keyframe(0.0, 2.0, 90.0));
+      timeline.add(keyframe(20.0, 20.0, 30.0));
+      assert_eq!(timeline.duration(), 20.0);
+
+      // clamped outside of the keyframes
+      let before = timeline.at(-5.0).unwrap();
+      assert_eq!((before.speed, before.direction_deg), (2.0, 90.0));
+      let after = timeline.at(25.0).unwrap();
+      assert_eq!((after.speed, after.direction_deg), (20.0, 30.0));
+
+      // 90 -> 350 turns by -100 degrees, not by 260
+      let turning = timeline.at(5.0).unwrap();
+      assert!((turning.speed - 6.0).abs() < 1e-5);
+      assert!((turning.direction_deg - 40.0).abs() < 1e-4, "{}", turning.direction_deg);
+
+      // 350 -> 30 turns through 0
+      let wrapping = timeline.at(17.5).unwrap();
+      assert!((wrapping.direction_deg - 20.0).abs() < 1e-4, "{}", wrapping.direction_deg);
+      let wrapping = timeline.at(11.25).unwrap();
+      assert!((wrapping.direction_deg - 355.0).abs() < 1e-4, "{}", wrapping.direction_deg);
+   }
+
+   #[test]
+   fn add_replaces_equal_time() {
+      let mut timeline = WindTimeline::default();
+      timeline.add(keyframe(0.0, 5.0, 0.0));
+      timeline.add(keyframe(10.0, 10.0, 0.0));
+      timeline.add(keyframe(10.0, 15.0, 45.0));
+      let keyframes = timeline.keyframes();
+      assert_eq!(keyframes.len(), 2);
+      assert_eq!((keyframes[1].speed, keyframes[1].direction_deg), (15.0, 45.0));
+   }
+}
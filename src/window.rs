@@ -1,7 +1,6 @@
 // Creation of graphical window, handing of window/keyboard/mouse events
 // Creation of user interface (ImGUI)
 
-use glium::backend::Facade;
 use glium::glutin;
 use glium::Surface;
 use glium::glutin::event::{Event, WindowEvent};
@@ -10,14 +9,13 @@ use imgui::{FontConfig, FontSource, Ui};
 use imgui_winit_support::{HiDpiMode};
 use std::path::PathBuf;
 use std::time::Instant;
-use gl;
+
 pub struct Window {
    pub event_loop: glutin::event_loop::EventLoop<()>,
    pub display: glium::Display,
    pub imgui: imgui::Context,
    pub platform: imgui_winit_support::WinitPlatform,
    pub renderer: imgui_glium_renderer::Renderer,
-   pub font_size: f32,
 }
 
 impl Window {
@@ -30,7 +28,7 @@ impl Window {
 
       let platform = init_winit(&display, &mut imgui);
 
-      let font_size = init_fonts(&mut imgui,
+      init_fonts(&mut imgui,
          font_size_pt,
          platform.hidpi_factor());
 
@@ -38,7 +36,7 @@ impl Window {
          .expect("Failed to initialize `imgui_glium_renderer::Renderer`");
 
       Self {
-         event_loop, display, imgui, platform, renderer, font_size
+         event_loop, display, imgui, platform, renderer
       }
    }
 
@@ -51,7 +49,7 @@ impl Window {
     where F : FnMut(&mut bool, &mut Ui, &glium::Display, &mut glium::Frame) + 'static,  {
       let Window {
           event_loop,
-          mut display,
+          display,
           mut imgui,
           mut platform,
           renderer: mut ui_renderer,
@@ -135,12 +133,8 @@ pub fn make_display(title: &str, width: u32, height: u32) -> DisplayCreationResu
       window_builder, context_builder, &event_loop)?;
 
    // init opengl
-   extern crate gl;
-   use gl::types::*;
-   unsafe {
-      gl::load_with(|s| display.gl_window().context().get_proc_address(s) );
-      gl::Viewport::load_with(|s| display.gl_window().context().get_proc_address(s));
-   }
+   gl::load_with(|s| display.gl_window().context().get_proc_address(s) );
+   gl::Viewport::load_with(|s| display.gl_window().context().get_proc_address(s));
 
    Ok((display, event_loop))
 }
@@ -154,7 +148,7 @@ fn init_winit(display: &glium::Display, imgui: &mut imgui::Context) -> imgui_win
    platform
 }
 
-fn init_fonts(imgui: &mut imgui::Context, font_size_pt: f64, hidpi_factor: f64) -> f32 {
+fn init_fonts(imgui: &mut imgui::Context, font_size_pt: f64, hidpi_factor: f64) {
    let font_size = (font_size_pt * hidpi_factor) as f32;
    imgui.fonts().add_font(&[
       FontSource::DefaultFontData {
@@ -166,5 +160,4 @@ fn init_fonts(imgui: &mut imgui::Context, font_size_pt: f64, hidpi_factor: f64)
    ]);
 
    imgui.io_mut().font_global_scale = (1.0 / hidpi_factor) as f32;
-   font_size
 }
\ No newline at end of file